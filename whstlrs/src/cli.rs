@@ -0,0 +1,388 @@
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::config::Config;
+use crate::export::{self, ExportOptions};
+use crate::input_manager::InputConnection;
+use crate::instrument::Instrument;
+use crate::output_manager::{OutputConnection, SYNTH_NAME};
+use crate::render::render_png;
+use crate::song::{MidiSelection, Song, SongFile};
+use crate::whistle::WhistleKey;
+
+pub const USAGE: &str = "Usage: whstlrs [<song>] [options]
+       whstlrs <command> ...
+
+Options:
+  --bpm <bpm>               tempo of the song, in quarter notes per minute
+  --transpose <semitones>   moves the song up, or down when negative
+  --whistle <key>           D, C, Eb, Bb or low-d
+  --instrument <name>       part of the name of a fingering chart
+  --input <port>            MIDI input, by index or by a part of its name
+  --output <port>           MIDI output, or synth for the built-in synth
+  --program <program>       General MIDI program, counted from zero
+  --library <dir>           directory with the songs
+  --track <track>           track of a MIDI file with the tune, counted from zero
+  --channel <channel>       MIDI channel of the tune, counted from zero
+
+Commands:
+  ports                                     lists the MIDI ports
+  validate <song>... [--whistle <key>] [--instrument <name>]
+  convert <song> <file.mid|file.notes|file.wav> [--transpose <semitones>]
+  render <song> <file.png> [--scale <scale>] [--instrument <name>]
+  export <song> <file.wav> [--bpm <bpm>] [--transpose <semitones>] [--click]
+  help
+
+The commands that read songs take --track and --channel as well.";
+
+/// The options of the app, for the song it opens with.
+#[derive(Debug, Default)]
+pub struct AppOptions {
+    pub song: Option<PathBuf>,
+    pub bpm: Option<f32>,
+    pub transpose: i8,
+    pub selection: MidiSelection,
+}
+
+/// The arguments, with the values of `--name=value` options split off.
+struct Args {
+    args: VecDeque<String>,
+    usage: &'static str,
+}
+
+impl Args {
+    fn new(args: &[String], usage: &'static str) -> Self {
+        let mut split = VecDeque::new();
+        for arg in args {
+            match arg.split_once('=') {
+                Some((name, value)) if arg.starts_with("--") => {
+                    split.push_back(name.to_string());
+                    split.push_back(value.to_string());
+                }
+                _ => split.push_back(arg.clone()),
+            }
+        }
+        Self { args: split, usage }
+    }
+
+    fn next(&mut self) -> Option<String> {
+        self.args.pop_front()
+    }
+
+    /// The value of `option`, the argument after it.
+    fn value<T: FromStr>(&mut self, option: &str) -> Result<T, String> {
+        self.next()
+            .and_then(|value| value.parse().ok())
+            .ok_or_else(|| format!("Invalid value for {}\n{}", option, self.usage))
+    }
+
+    /// Reads `--track` and `--channel` into `selection`, false for the other options.
+    fn midi_selection(
+        &mut self,
+        option: &str,
+        selection: &mut MidiSelection,
+    ) -> Result<bool, String> {
+        match option {
+            "--track" => selection.track = Some(self.value(option)?),
+            "--channel" => {
+                let channel: u8 = self.value(option)?;
+                if channel > 15 {
+                    return Err(format!("Invalid MIDI channel {}, 0 to 15", channel));
+                }
+                selection.channel = Some(channel);
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    fn unknown(&self, arg: &str) -> String {
+        format!("Unknown option {}\n{}", arg, self.usage)
+    }
+}
+
+/// Runs the command in `args`, without opening a window. `None` when there
+/// is no command and the app starts.
+pub fn run_command(args: &[String]) -> Option<Result<(), String>> {
+    let (command, args) = args.split_first()?;
+    Some(match command.as_str() {
+        "ports" => ports_command(),
+        "validate" => validate_command(args),
+        "convert" => convert_command(args),
+        "render" => render_command(args),
+        "export" => export_command(args),
+        "help" | "--help" | "-h" => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        _ => return None,
+    })
+}
+
+/// Reads the options of the app. Those that are settings go in `config`,
+/// before the saved ones.
+pub fn app_options(args: &[String], config: &mut Config) -> Result<AppOptions, String> {
+    let mut options = AppOptions::default();
+    let mut args = Args::new(args, USAGE);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--bpm" => options.bpm = Some(args.value(&arg)?),
+            "--transpose" => options.transpose = args.value(&arg)?,
+            "--whistle" => config.whistle_key = whistle_key(&args.value::<String>(&arg)?)?,
+            "--instrument" => config.instrument = Some(args.value(&arg)?),
+            "--input" => config.input_port = Some(args.value(&arg)?),
+            "--output" => config.output_port = Some(args.value(&arg)?),
+            "--program" => config.program = args.value(&arg)?,
+            "--library" => config.library_dir = Some(args.value(&arg)?),
+            _ if args.midi_selection(&arg, &mut options.selection)? => (),
+            _ if arg.starts_with("--") => return Err(args.unknown(&arg)),
+            _ if options.song.is_none() => options.song = Some(arg.into()),
+            _ => return Err(format!("Only one song can be opened\n{}", USAGE)),
+        }
+    }
+    if options
+        .bpm
+        .is_some_and(|bpm| !bpm.is_finite() || bpm <= 0.0)
+    {
+        return Err(format!("Invalid tempo {:?}", options.bpm));
+    }
+    Ok(options)
+}
+
+fn whistle_key(name: &str) -> Result<WhistleKey, String> {
+    WhistleKey::parse(name).ok_or_else(|| format!("Unknown whistle key {}", name))
+}
+
+fn instrument(name: &str) -> Result<Instrument, String> {
+    Instrument::find(name).ok_or_else(|| format!("Unknown instrument {}", name))
+}
+
+/// `whstlrs ports`, the MIDI ports to pick with `--input` and `--output`.
+fn ports_command() -> Result<(), String> {
+    println!("MIDI inputs:");
+    for (i, name) in InputConnection::port_names()?.iter().enumerate() {
+        println!("  {}: {}", i, name);
+    }
+    println!("MIDI outputs:");
+    for (i, name) in OutputConnection::port_names()?.iter().enumerate() {
+        println!("  {}: {}", i, name);
+    }
+    println!("  synth: {}", SYNTH_NAME);
+    Ok(())
+}
+
+/// `whstlrs validate <song>...`, checks that the songs can be read, that the
+/// sheet music has their notes and that the instrument can play them.
+fn validate_command(args: &[String]) -> Result<(), String> {
+    let usage = "Usage: whstlrs validate <song>... [--whistle <key>] [--instrument <name>]";
+    let mut args = Args::new(args, usage);
+    let mut key = WhistleKey::default();
+    let mut chart = Instrument::default();
+    let mut selection = MidiSelection::default();
+    let mut paths = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--whistle" => key = whistle_key(&args.value::<String>(&arg)?)?,
+            "--instrument" => chart = instrument(&args.value::<String>(&arg)?)?,
+            _ if args.midi_selection(&arg, &mut selection)? => (),
+            _ if arg.starts_with("--") => return Err(args.unknown(&arg)),
+            _ => paths.push(arg),
+        }
+    }
+    if paths.is_empty() {
+        return Err(usage.to_string());
+    }
+    let failed = paths
+        .iter()
+        .filter(|path| {
+            let problems = validate(Path::new(path), selection, &chart, key);
+            for problem in problems.iter() {
+                println!("{}: {}", path, problem);
+            }
+            !problems.is_empty()
+        })
+        .count();
+    match failed {
+        0 => Ok(()),
+        _ => Err(format!("{} of {} songs have problems", failed, paths.len())),
+    }
+}
+
+/// What is wrong with the song, a summary is printed when nothing is.
+fn validate(
+    path: &Path,
+    selection: MidiSelection,
+    instrument: &Instrument,
+    key: WhistleKey,
+) -> Vec<String> {
+    let song = match Song::load_with_selection(path, selection) {
+        Ok(song) => song,
+        Err(e) => return vec![e],
+    };
+    let file = &song.file;
+    let mut problems = Vec::new();
+    if file.notes.is_empty() {
+        problems.push("No notes".to_string());
+    }
+    if let Some(svg) = &song.svg {
+        let fontdb = resvg::usvg::fontdb::Database::new();
+        match resvg::usvg::Tree::from_data(svg, &resvg::usvg::Options::default(), &fontdb) {
+            Ok(tree) => {
+                let missing = file
+                    .notes
+                    .iter()
+                    .filter(|note| tree.node_by_id(&note.notehead_id).is_none())
+                    .count();
+                if missing > 0 {
+                    problems.push(format!("{} notes are not in the sheet music", missing));
+                }
+            }
+            Err(e) => problems.push(format!("Could not read the sheet music: {}", e)),
+        }
+    }
+    let unplayable = file
+        .notes
+        .iter()
+        .filter(|note| instrument.fingerings(note.midi_key, key).is_empty())
+        .count();
+    if unplayable > 0 {
+        problems.push(format!(
+            "{} notes are out of range of the {} in {}, --transpose {} fits it",
+            unplayable,
+            instrument.name,
+            key.name(),
+            instrument.fit(file, key)
+        ));
+    }
+    if problems.is_empty() {
+        println!(
+            "{}: {}, {} notes, {} bars{}",
+            path.display(),
+            file.info.title.as_deref().unwrap_or(&file.name),
+            file.notes.len(),
+            file.bars.len(),
+            if song.svg.is_some() {
+                ", with sheet music"
+            } else {
+                ""
+            }
+        );
+    }
+    problems
+}
+
+/// `whstlrs convert <song> <output>`, writes the song in the format of the
+/// extension of the output.
+fn convert_command(args: &[String]) -> Result<(), String> {
+    let usage =
+        "Usage: whstlrs convert <song> <file.mid|file.notes|file.wav> [--transpose <semitones>]";
+    let mut args = Args::new(args, usage);
+    let mut transpose = 0;
+    let mut selection = MidiSelection::default();
+    let mut paths = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--transpose" => transpose = args.value(&arg)?,
+            _ if args.midi_selection(&arg, &mut selection)? => (),
+            _ if arg.starts_with("--") => return Err(args.unknown(&arg)),
+            _ => paths.push(arg),
+        }
+    }
+    let [song, output] = paths.as_slice() else {
+        return Err(usage.to_string());
+    };
+    let mut song =
+        SongFile::with_selection(song, selection).map_err(|e| format!("{}: {}", song, e))?;
+    let extension = Path::new(output)
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase());
+    match extension.as_deref() {
+        Some("mid" | "midi") => {
+            song.transpose(transpose, 0)?;
+            song.save_midi(output)?;
+        }
+        Some("notes") => {
+            song.transpose(transpose, 0)?;
+            song.save_notes(output)?;
+            let info = &song.info;
+            if info.tempo.is_some()
+                || info.title.is_some()
+                || info.key.is_some()
+                || info.meter.is_some()
+            {
+                log::warn!(
+                    "{} has the notes only: it plays at a whole note a second, without the tempo, title, key and meter of the song",
+                    output
+                );
+            }
+        }
+        Some("wav") => {
+            let options = ExportOptions {
+                transpose,
+                ..Default::default()
+            };
+            export::export_wav(&song, output, &options)?;
+        }
+        _ => return Err(format!("Unknown format of {}\n{}", output, usage)),
+    }
+    log::info!("{} written", output);
+    Ok(())
+}
+
+/// `whstlrs render <song> <file.png>`, draws the sheet music.
+fn render_command(args: &[String]) -> Result<(), String> {
+    let usage = "Usage: whstlrs render <song> <file.png> [--scale <scale>] [--instrument <name>]";
+    let mut args = Args::new(args, usage);
+    let mut scale: f32 = 1.0;
+    let mut chart = Instrument::default();
+    let mut selection = MidiSelection::default();
+    let mut paths = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--scale" => scale = args.value(&arg)?,
+            "--instrument" => chart = instrument(&args.value::<String>(&arg)?)?,
+            _ if args.midi_selection(&arg, &mut selection)? => (),
+            _ if arg.starts_with("--") => return Err(args.unknown(&arg)),
+            _ => paths.push(arg),
+        }
+    }
+    let [song, png] = paths.as_slice() else {
+        return Err(usage.to_string());
+    };
+    if !scale.is_finite() || scale <= 0.0 {
+        return Err(format!("Invalid scale {}", scale));
+    }
+    let song =
+        Song::load_with_selection(song, selection).map_err(|e| format!("{}: {}", song, e))?;
+    render_png(&song, chart.holes, Path::new(png), scale)?;
+    log::info!("{} written", png);
+    Ok(())
+}
+
+/// `whstlrs export <song> <file.wav>`, renders the song without opening a window.
+fn export_command(args: &[String]) -> Result<(), String> {
+    let usage =
+        "Usage: whstlrs export <song> <file.wav> [--bpm <bpm>] [--transpose <semitones>] [--click]";
+    let mut args = Args::new(args, usage);
+    let mut options = ExportOptions::default();
+    let mut selection = MidiSelection::default();
+    let mut paths = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--bpm" => options.bpm = Some(args.value(&arg)?),
+            "--transpose" => options.transpose = args.value(&arg)?,
+            "--click" => options.click = true,
+            _ if args.midi_selection(&arg, &mut selection)? => (),
+            _ if arg.starts_with("--") => return Err(args.unknown(&arg)),
+            _ => paths.push(arg),
+        }
+    }
+    let [song, wav] = paths.as_slice() else {
+        return Err(usage.to_string());
+    };
+    let song = SongFile::with_selection(song, selection)?;
+    export::export_wav(&song, wav, &options)?;
+    log::info!("{} written", wav);
+    Ok(())
+}
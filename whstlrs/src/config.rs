@@ -0,0 +1,394 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use resvg::usvg::Color;
+
+use crate::input_manager::PortSelector;
+use crate::library::Library;
+use crate::output_manager::PROGRAM_WHISTLE;
+use crate::song::score::TimingWindows;
+use crate::song::{MAX_SPEED, MIN_SPEED};
+use crate::utils::dirs;
+use crate::whistle::WhistleKey;
+
+/// File in the config directory with the settings.
+const CONFIG_FILE: &str = "settings.csv";
+/// Longest count-in before a song.
+pub const MAX_COUNT_IN_BARS: u32 = 2;
+
+/// The settings kept between runs. The file has a `name,value` row per
+/// setting, settings that are left out keep their default.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    /// MIDI input port, by index or by a part of its name. `None` connects to
+    /// `last_input`.
+    pub input_port: Option<String>,
+    /// Name of the last connected MIDI input.
+    pub last_input: Option<String>,
+    /// MIDI output port, or "synth" for the built-in synth.
+    pub output_port: Option<String>,
+    /// General MIDI program of the song, counted from zero.
+    pub program: u8,
+    pub window_width: u32,
+    pub window_height: u32,
+    pub whistle_key: WhistleKey,
+    /// Part of the name of the instrument.
+    pub instrument: Option<String>,
+    /// Directory with the songs, see `Library::default_dir` for the default.
+    pub library_dir: Option<PathBuf>,
+    /// Playback speed the songs start with, 1.0 is the tempo of the song.
+    pub speed: f32,
+    /// Speed a loop region speeds up to, when it speeds up after each
+    /// successful repetition.
+    pub loop_target_speed: f32,
+    pub wait_mode: bool,
+    pub metronome: bool,
+    pub count_in_bars: u32,
+    /// Colour of the notes that are played.
+    pub note_color: Color,
+    /// Colour of the finger holes to close.
+    pub hole_color: Color,
+    /// How close to the notes the played notes are scored, in milliseconds in
+    /// the file.
+    pub timing_windows: TimingWindows,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            input_port: None,
+            last_input: None,
+            output_port: None,
+            program: PROGRAM_WHISTLE,
+            window_width: 1280,
+            window_height: 720,
+            whistle_key: WhistleKey::default(),
+            instrument: None,
+            library_dir: None,
+            speed: 1.0,
+            loop_target_speed: 1.0,
+            wait_mode: false,
+            metronome: false,
+            count_in_bars: 1,
+            note_color: Color::new_rgb(0, 0, 255),
+            hole_color: Color::new_rgb(255, 0, 0),
+            timing_windows: TimingWindows::default(),
+        }
+    }
+}
+
+impl Config {
+    pub fn path() -> PathBuf {
+        dirs::config_dir().join(CONFIG_FILE)
+    }
+
+    /// Loads the settings from the config directory, the defaults when there are none.
+    pub fn load() -> Self {
+        Self::load_from(Self::path()).unwrap_or_else(|e| {
+            log::error!("{}", e);
+            Self::default()
+        })
+    }
+
+    pub fn load_from<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref();
+        let mut config = Self::default();
+        if !path.exists() {
+            return Ok(config);
+        }
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .comment(Some(b'#'))
+            .trim(csv::Trim::All)
+            .from_path(path)
+            .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        for record in reader.records() {
+            let record = record.map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+            let line = record.position().map_or(0, |p| p.line());
+            let name = record.get(0).unwrap_or_default();
+            let value = record.get(1).unwrap_or_default();
+            // keep the rest of the settings
+            if let Err(e) = config.set(name, value) {
+                log::warn!("{} line {}: {}", path.display(), line, e);
+            }
+        }
+        let windows = config.timing_windows;
+        if windows.hit > windows.ok {
+            log::warn!(
+                "{}: the hit window of {} ms is wider than the ok window of {} ms, using the defaults",
+                path.display(),
+                windows.hit.as_millis(),
+                windows.ok.as_millis()
+            );
+            config.timing_windows = TimingWindows::default();
+        }
+        Ok(config)
+    }
+
+    /// Saves the settings to the config directory.
+    pub fn save(&self) -> Result<(), String> {
+        self.save_to(Self::path())
+    }
+
+    pub fn save_to<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let path = path.as_ref();
+        let write_error =
+            |e: &dyn std::fmt::Display| format!("Could not write {}: {}", path.display(), e);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| write_error(&e))?;
+        }
+        let mut writer = csv::WriterBuilder::new()
+            .has_headers(false)
+            .from_path(path)
+            .map_err(|e| write_error(&e))?;
+        for (name, value) in self.values() {
+            writer
+                .write_record([name, &value])
+                .map_err(|e| write_error(&e))?;
+        }
+        writer.flush().map_err(|e| write_error(&e))
+    }
+
+    /// The settings by name, as they are written to the file.
+    fn values(&self) -> Vec<(&'static str, String)> {
+        let optional = |value: &Option<String>| value.clone().unwrap_or_default();
+        vec![
+            ("input", optional(&self.input_port)),
+            ("last_input", optional(&self.last_input)),
+            ("output", optional(&self.output_port)),
+            ("program", self.program.to_string()),
+            ("window_width", self.window_width.to_string()),
+            ("window_height", self.window_height.to_string()),
+            ("whistle", self.whistle_key.name().to_string()),
+            ("instrument", optional(&self.instrument)),
+            (
+                "library",
+                self.library_dir
+                    .as_ref()
+                    .map(|dir| dir.display().to_string())
+                    .unwrap_or_default(),
+            ),
+            ("speed", self.speed.to_string()),
+            ("loop_target_speed", self.loop_target_speed.to_string()),
+            ("wait_mode", self.wait_mode.to_string()),
+            ("metronome", self.metronome.to_string()),
+            ("count_in_bars", self.count_in_bars.to_string()),
+            ("note_color", format_color(self.note_color)),
+            ("hole_color", format_color(self.hole_color)),
+            (
+                "hit_window",
+                self.timing_windows.hit.as_millis().to_string(),
+            ),
+            ("ok_window", self.timing_windows.ok.as_millis().to_string()),
+        ]
+    }
+
+    /// Sets a setting by its name in the file, an empty value resets an
+    /// optional setting.
+    fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let invalid = || format!("Invalid {} {:?}", name, value);
+        let optional = |value: &str| (!value.is_empty()).then(|| value.to_string());
+        match name {
+            "input" => self.input_port = optional(value),
+            "last_input" => self.last_input = optional(value),
+            "output" => self.output_port = optional(value),
+            "program" => {
+                self.program = value
+                    .parse()
+                    .ok()
+                    .filter(|&p| p < 128)
+                    .ok_or_else(invalid)?
+            }
+            "window_width" => self.window_width = value.parse().map_err(|_| invalid())?,
+            "window_height" => self.window_height = value.parse().map_err(|_| invalid())?,
+            "whistle" => self.whistle_key = WhistleKey::parse(value).ok_or_else(invalid)?,
+            "instrument" => self.instrument = optional(value),
+            "library" => self.library_dir = optional(value).map(PathBuf::from),
+            "speed" => self.speed = speed(value).ok_or_else(invalid)?,
+            "loop_target_speed" => self.loop_target_speed = speed(value).ok_or_else(invalid)?,
+            "wait_mode" => self.wait_mode = value.parse().map_err(|_| invalid())?,
+            "metronome" => self.metronome = value.parse().map_err(|_| invalid())?,
+            "count_in_bars" => {
+                self.count_in_bars = value
+                    .parse()
+                    .ok()
+                    .filter(|&bars| bars <= MAX_COUNT_IN_BARS)
+                    .ok_or_else(invalid)?
+            }
+            "note_color" => self.note_color = parse_color(value).ok_or_else(invalid)?,
+            "hole_color" => self.hole_color = parse_color(value).ok_or_else(invalid)?,
+            "hit_window" => self.timing_windows.hit = millis(value).ok_or_else(invalid)?,
+            "ok_window" => self.timing_windows.ok = millis(value).ok_or_else(invalid)?,
+            _ => return Err(format!("Unknown setting {:?}", name)),
+        }
+        Ok(())
+    }
+
+    /// Takes over the settings that differ between `from` and `to`.
+    pub fn apply_changes(&mut self, from: &Config, to: &Config) {
+        for ((name, old), (_, new)) in from.values().into_iter().zip(to.values()) {
+            if old != new {
+                if let Err(e) = self.set(name, &new) {
+                    log::warn!("{}", e);
+                }
+            }
+        }
+    }
+
+    pub fn library_dir(&self) -> PathBuf {
+        self.library_dir
+            .clone()
+            .unwrap_or_else(Library::default_dir)
+    }
+
+    pub fn input_port(&self) -> Option<PortSelector> {
+        self.input_port.as_deref().map(PortSelector::parse)
+    }
+
+    pub fn output_port(&self) -> Option<PortSelector> {
+        self.output_port.as_deref().map(PortSelector::parse)
+    }
+}
+
+/// A playback speed within the range of the playback.
+fn speed(value: &str) -> Option<f32> {
+    value
+        .parse()
+        .ok()
+        .filter(|speed| (MIN_SPEED..=MAX_SPEED).contains(speed))
+}
+
+/// A positive number of milliseconds.
+fn millis(value: &str) -> Option<Duration> {
+    value
+        .parse()
+        .ok()
+        .filter(|&millis| millis > 0)
+        .map(Duration::from_millis)
+}
+
+/// A colour like "#ff8000".
+pub fn parse_color(text: &str) -> Option<Color> {
+    let hex = text.trim().strip_prefix('#')?;
+    if hex.len() != 6 {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+    Some(Color::new_rgb(channel(0)?, channel(2)?, channel(4)?))
+}
+
+pub fn format_color(color: Color) -> String {
+    format!("#{:02x}{:02x}{:02x}", color.red, color.green, color.blue)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_file(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("whstlrs-{}-{}.csv", std::process::id(), name))
+    }
+
+    #[test]
+    fn colors() {
+        let color = Color::new_rgb(255, 128, 0);
+        assert_eq!(format_color(color), "#ff8000");
+        assert_eq!(parse_color(" #FF8000 "), Some(color));
+        assert_eq!(parse_color(&format_color(color)), Some(color));
+        assert_eq!(parse_color("ff8000"), None);
+        assert_eq!(parse_color("#ff80"), None);
+        assert_eq!(parse_color("#gg8000"), None);
+        assert_eq!(parse_color("#ff80€"), None);
+    }
+
+    #[test]
+    fn save_and_load() {
+        let config = Config {
+            input_port: Some("warbl".to_string()),
+            last_input: Some("WARBL:WARBL MIDI 1 20:0".to_string()),
+            output_port: Some("synth".to_string()),
+            program: 73,
+            whistle_key: WhistleKey::Eb,
+            library_dir: Some(PathBuf::from("/tunes")),
+            speed: 0.85,
+            loop_target_speed: 1.25,
+            wait_mode: true,
+            count_in_bars: 2,
+            note_color: Color::new_rgb(128, 0, 192),
+            timing_windows: TimingWindows {
+                hit: Duration::from_millis(50),
+                ok: Duration::from_millis(120),
+            },
+            ..Default::default()
+        };
+        let path = temp_file("save-and-load");
+        config.save_to(&path).unwrap();
+        let loaded = Config::load_from(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.unwrap(), config);
+    }
+
+    #[test]
+    fn malformed_lines_keep_the_defaults() {
+        let path = temp_file("malformed");
+        let text = "# settings\nprogram,300\nspeed,-1\nloop_target_speed,9\nwhistle,Z\nhole_color,red\nhit_window,0\n\
+                    count_in_bars,8\nunknown,1\nwait_mode\nmetronome,true\n";
+        fs::write(&path, text).unwrap();
+        let loaded = Config::load_from(&path);
+        fs::remove_file(&path).unwrap();
+        let expected = Config {
+            metronome: true,
+            ..Default::default()
+        };
+        assert_eq!(loaded.unwrap(), expected);
+    }
+
+    #[test]
+    fn hit_window_wider_than_ok_window_is_the_default() {
+        let path = temp_file("windows");
+        fs::write(&path, "hit_window,300\nok_window,100\nwait_mode,true\n").unwrap();
+        let loaded = Config::load_from(&path);
+        fs::remove_file(&path).unwrap();
+        let expected = Config {
+            wait_mode: true,
+            ..Default::default()
+        };
+        assert_eq!(loaded.unwrap(), expected);
+    }
+
+    #[test]
+    fn missing_file_is_the_defaults() {
+        let loaded = Config::load_from(temp_file("missing")).unwrap();
+        assert_eq!(loaded, Config::default());
+    }
+
+    #[test]
+    fn empty_values_reset_optional_settings() {
+        let mut config = Config {
+            instrument: Some("flute".to_string()),
+            ..Default::default()
+        };
+        config.set("instrument", "").unwrap();
+        assert_eq!(config.instrument, None);
+        assert!(config.set("window_width", "").is_err());
+    }
+
+    #[test]
+    fn only_changed_settings_are_taken_over() {
+        let mut saved = Config::default();
+        // a command line option on top of the saved settings
+        let old = Config {
+            whistle_key: WhistleKey::C,
+            ..saved.clone()
+        };
+        let new = Config {
+            speed: 0.5,
+            ..old.clone()
+        };
+        saved.apply_changes(&old, &new);
+        assert_eq!(saved.whistle_key, WhistleKey::D);
+        assert_eq!(saved.speed, 0.5);
+    }
+}
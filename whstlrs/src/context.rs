@@ -1,7 +1,11 @@
-use crate::input_manager::InputConnection;
+use crate::config::Config;
+use crate::input_manager::{InputConnection, PortSelector};
+use crate::instrument::Instrument;
 use crate::output_manager::OutputConnection;
-use crate::song::{Song, SongFile};
+use crate::song::Song;
 use crate::utils::window::WindowState;
+use crate::virtual_input::VirtualInput;
+use crate::whistle::WhistleKey;
 use crate::WhstlrsEvent;
 use std::sync::Arc;
 use wgpu_jumpstart::{wgpu, Gpu, TransformUniform, Uniform};
@@ -14,9 +18,17 @@ pub struct Context {
     pub gpu: Gpu,
 
     pub transform: Uniform<TransformUniform>,
+    /// The song to play at the start, and then the last one picked.
     pub song: Option<Song>,
-    //pub output_connection: OutputConnection,
+    pub output_connection: OutputConnection,
     pub input_connection: InputConnection,
+    pub virtual_input: VirtualInput,
+    pub instrument: Instrument,
+    pub whistle_key: WhistleKey,
+    /// The settings in use: the saved ones with the command line options on top.
+    pub config: Config,
+    /// The settings as saved, without the command line options.
+    pub saved_config: Config,
 
     pub proxy: EventLoopProxy<WhstlrsEvent>,
 }
@@ -27,6 +39,8 @@ impl Context {
         window_state: WindowState,
         proxy: EventLoopProxy<WhstlrsEvent>,
         gpu: Gpu,
+        config: Config,
+        saved_config: Config,
     ) -> Self {
         let transform_uniform = Uniform::new(
             &gpu.device,
@@ -34,30 +48,105 @@ impl Context {
             wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
         );
 
-        let song_file = if let Ok(s) = SongFile::from_str(
-            include_str!("../../contrib/starofthecountydown/starofthecountydown.notes"),
-            "starofthecountydown".to_string(),
-        ) {
-            Some(s)
-        } else {
-            None
+        let instrument = match &config.instrument {
+            Some(name) => Instrument::find(name).unwrap_or_else(|| {
+                log::warn!("Unknown instrument {}", name);
+                Instrument::default()
+            }),
+            None => Instrument::default(),
         };
-        //let output_connection: OutputConnection = OutputConnection::new();
-
-        let mut input_connection = InputConnection::new(proxy.clone());
-        input_connection.connect_input();
-        Self {
+        let mut virtual_input = VirtualInput::new(proxy.clone());
+        virtual_input.set_instrument(instrument.clone(), config.whistle_key);
+        let mut ctx = Self {
             window,
             window_state,
             gpu,
             transform: transform_uniform,
-            song: song_file.map(Song::new),
-            //output_connection,
-            input_connection,
+            song: None,
+            output_connection: OutputConnection::new(),
+            input_connection: InputConnection::new(proxy.clone()),
+            virtual_input,
+            instrument,
+            whistle_key: config.whistle_key,
+            config,
+            saved_config,
             proxy,
+        };
+        ctx.connect_output();
+        ctx.connect_input();
+        ctx
+    }
+
+    /// Connects to the MIDI output of the config, or else the built-in synth.
+    pub fn connect_output(&mut self) {
+        let output_connection = &mut self.output_connection;
+        output_connection.set_program(self.config.program);
+        let connected = match self.config.output_port() {
+            Some(PortSelector::Name(name)) if name == "synth" => output_connection.connect_synth(),
+            port => output_connection
+                .connect(port.as_ref())
+                .map(|_| ())
+                .or_else(|e| {
+                    log::info!("{}, using the built-in synth", e);
+                    output_connection.connect_synth()
+                }),
+        };
+        if let Err(e) = connected {
+            log::warn!("{}, playing without sound", e);
         }
     }
 
+    /// Connects to the MIDI input of the config, the keyboard plays without one.
+    pub fn connect_input(&mut self) {
+        let selector = self.config.input_port();
+        let last_input = self.config.last_input.as_deref();
+        match self
+            .input_connection
+            .connect_input(selector.as_ref(), last_input)
+        {
+            Ok(name) => self.remember_input(name),
+            Err(e) => {
+                log::warn!("{}, playing with the keyboard", e);
+                self.virtual_input.set_enabled(true);
+            }
+        }
+    }
+
+    /// Reconnects the MIDI input after the ports changed, see
+    /// `InputConnection::ports_changed`.
+    pub fn ports_changed(&mut self, names: Vec<String>) {
+        if let Some(name) = self.input_connection.ports_changed(names) {
+            self.remember_input(name);
+        }
+    }
+
+    /// Saves the connected MIDI input in the settings, to connect to it the
+    /// next time.
+    fn remember_input(&mut self, name: String) {
+        if self.saved_config.last_input.as_ref() == Some(&name) {
+            return;
+        }
+        self.config.last_input = Some(name.clone());
+        self.saved_config.last_input = Some(name);
+        if let Err(e) = self.saved_config.save() {
+            log::warn!("{}", e);
+        }
+    }
+
+    /// Picks the instrument that is played, for the fingerings and the keyboard whistle.
+    pub fn set_instrument(&mut self, instrument: Instrument) {
+        self.instrument = instrument;
+        self.virtual_input
+            .set_instrument(self.instrument.clone(), self.whistle_key);
+    }
+
+    /// Picks the key of the whistle that is played.
+    pub fn set_whistle_key(&mut self, whistle_key: WhistleKey) {
+        self.whistle_key = whistle_key;
+        self.virtual_input
+            .set_instrument(self.instrument.clone(), self.whistle_key);
+    }
+
     pub fn resize(&mut self) {
         self.transform.data.update(
             self.window_state.logical_size.width,
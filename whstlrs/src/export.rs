@@ -0,0 +1,97 @@
+//! Renders a song to a WAV file with the built-in synth, without a window.
+
+use std::path::Path;
+use std::time::Duration;
+
+use midly::{num::u7, MidiMessage};
+
+use crate::output_manager::{CLICK, CLICK_ACCENT, DRUM_CHANNEL};
+use crate::song::SongFile;
+use crate::synth::{self, Synth};
+
+const CLICK_VELOCITY: u8 = 90;
+
+#[derive(Debug, Clone, Default)]
+pub struct ExportOptions {
+    /// Tempo in quarter notes per minute, the tempo of the song when not set.
+    pub bpm: Option<f32>,
+    /// Semitones up, or down when negative.
+    pub transpose: i8,
+    /// Adds a click on every beat.
+    pub click: bool,
+}
+
+pub fn export_wav<P: AsRef<Path>>(
+    song: &SongFile,
+    path: P,
+    options: &ExportOptions,
+) -> Result<(), String> {
+    let speed = match (options.bpm, song.info.tempo) {
+        (Some(bpm), Some(song_bpm)) => bpm / song_bpm,
+        (Some(bpm), None) => {
+            return Err(format!("{} has no tempo to play at {} bpm", song.name, bpm))
+        }
+        (None, _) => 1.0,
+    };
+    if !(speed.is_finite() && speed > 0.0) {
+        return Err(format!("Invalid tempo {:?}", options.bpm));
+    }
+
+    // notes moved out of the MIDI range are left out
+    let mut events: Vec<(Duration, u8, MidiMessage)> = song
+        .events
+        .iter()
+        .filter_map(|event| {
+            let message = match event.message {
+                // the percussion channel plays instruments, not pitches
+                _ if event.channel == DRUM_CHANNEL => event.message,
+                MidiMessage::NoteOn { key, vel } => MidiMessage::NoteOn {
+                    key: transpose(key, options.transpose)?,
+                    vel,
+                },
+                MidiMessage::NoteOff { key, vel } => MidiMessage::NoteOff {
+                    key: transpose(key, options.transpose)?,
+                    vel,
+                },
+                message => message,
+            };
+            Some((event.timestamp, event.channel, message))
+        })
+        .collect();
+    let dropped = song.events.len() - events.len();
+    if dropped > 0 {
+        log::warn!(
+            "{} notes of {} are out of range, transposed by {} semitones",
+            dropped.div_ceil(2),
+            song.name,
+            options.transpose
+        );
+    }
+    if options.click {
+        let beats = song.beats();
+        if beats.is_empty() {
+            log::warn!("{} has no tempo to click the beat of", song.name);
+        }
+        for (time, downbeat) in beats {
+            let key = u7::new(if downbeat { CLICK_ACCENT } else { CLICK });
+            let vel = u7::new(CLICK_VELOCITY);
+            events.push((time, DRUM_CHANNEL, MidiMessage::NoteOn { key, vel }));
+            events.push((time, DRUM_CHANNEL, MidiMessage::NoteOff { key, vel }));
+        }
+        events.sort_by_key(|(time, ..)| *time);
+    }
+
+    let end = events.last().map_or(Duration::ZERO, |(time, ..)| *time);
+    let samples = Synth::new(synth::SAMPLE_RATE).render_offline(
+        events
+            .iter()
+            .map(|(time, channel, message)| (time.div_f32(speed), *channel, message)),
+        end.div_f32(speed),
+    );
+    synth::write_wav(path, &samples, synth::SAMPLE_RATE)
+}
+
+fn transpose(key: u7, semitones: i8) -> Option<u7> {
+    let key = key.as_int() as i16 + semitones as i16;
+    (0..=127).contains(&key).then(|| u7::new(key as u8))
+}
@@ -0,0 +1,187 @@
+//! Practice history, the results of every played session kept per song.
+
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::song::score::{NoteResult, Scorer};
+use crate::song::SongFile;
+use crate::utils::dirs;
+
+const HISTORY_FILE: &str = "history.csv";
+
+#[derive(Debug, Clone)]
+pub struct Session {
+    /// `SongFile::name` of the played song.
+    pub song: String,
+    /// Seconds since the Unix epoch.
+    pub date: u64,
+    /// Tempo played at, unknown for songs without a tempo.
+    pub bpm: Option<f32>,
+    pub accuracy: f32,
+    /// Wrong and missed notes per bar, counting bars from zero.
+    pub bar_errors: Vec<(usize, u32)>,
+}
+
+impl Session {
+    pub fn new(song: &SongFile, bpm: Option<f32>, score: &Scorer) -> Self {
+        let mut errors: HashMap<usize, u32> = HashMap::new();
+        for (timestamp, result) in score.results() {
+            if matches!(result, Some(NoteResult::Wrong | NoteResult::Missed)) {
+                *errors.entry(song.bar_at(timestamp)).or_default() += 1;
+            }
+        }
+        let mut bar_errors: Vec<(usize, u32)> = errors.into_iter().collect();
+        bar_errors.sort();
+        Self {
+            song: song.name.clone(),
+            date: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            bpm,
+            accuracy: score.summary().accuracy,
+            bar_errors,
+        }
+    }
+
+    fn from_record(record: &csv::StringRecord) -> Result<Self, String> {
+        let field = |i: usize| record.get(i).ok_or(format!("Missing field {}", i + 1));
+        let bar_errors = field(4)?
+            .split_whitespace()
+            .map(|pair| {
+                let (bar, count) = pair.split_once(':')?;
+                Some((bar.parse().ok()?, count.parse().ok()?))
+            })
+            .collect::<Option<Vec<(usize, u32)>>>()
+            .ok_or("Invalid bar errors")?;
+        Ok(Self {
+            song: field(0)?.to_string(),
+            date: field(1)?.parse().map_err(|_| "Invalid date")?,
+            bpm: match field(2)? {
+                "" => None,
+                bpm => Some(bpm.parse().map_err(|_| "Invalid tempo")?),
+            },
+            accuracy: field(3)?.parse().map_err(|_| "Invalid accuracy")?,
+            bar_errors,
+        })
+    }
+
+    fn record(&self) -> [String; 5] {
+        let bar_errors: Vec<String> = self
+            .bar_errors
+            .iter()
+            .map(|(bar, count)| format!("{}:{}", bar, count))
+            .collect();
+        [
+            self.song.clone(),
+            self.date.to_string(),
+            self.bpm
+                .map(|bpm| format!("{:.1}", bpm))
+                .unwrap_or_default(),
+            format!("{:.4}", self.accuracy),
+            bar_errors.join(" "),
+        ]
+    }
+}
+
+pub struct History {
+    path: PathBuf,
+    sessions: Vec<Session>,
+}
+
+impl History {
+    /// Loads the history from the data directory, starting empty when it can't be read.
+    pub fn load() -> Self {
+        let path = dirs::data_dir().join(HISTORY_FILE);
+        Self::load_from(&path).unwrap_or_else(|e| {
+            log::error!("{}", e);
+            Self {
+                path,
+                sessions: Vec::new(),
+            }
+        })
+    }
+
+    pub fn load_from<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref().to_path_buf();
+        if !path.exists() {
+            return Ok(Self {
+                path,
+                sessions: Vec::new(),
+            });
+        }
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .from_path(&path)
+            .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        let mut sessions = Vec::new();
+        for (line, record) in reader.records().enumerate() {
+            let record = record.map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+            match Session::from_record(&record) {
+                Ok(session) => sessions.push(session),
+                // keep the rest of the history
+                Err(e) => log::warn!("{} line {}: {}", path.display(), line + 1, e),
+            }
+        }
+        Ok(Self { path, sessions })
+    }
+
+    /// Adds a session and appends it to the history file.
+    pub fn add(&mut self, session: Session) -> Result<(), String> {
+        let write_error =
+            |e: &dyn std::fmt::Display| format!("Could not write {}: {}", self.path.display(), e);
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).map_err(|e| write_error(&e))?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|e| write_error(&e))?;
+        let mut writer = csv::WriterBuilder::new()
+            .has_headers(false)
+            .from_writer(file);
+        writer
+            .write_record(session.record())
+            .and_then(|_| writer.flush().map_err(csv::Error::from))
+            .map_err(|e| write_error(&e))?;
+        self.sessions.push(session);
+        Ok(())
+    }
+
+    /// Sessions of a song, oldest first.
+    pub fn sessions<'a>(&'a self, song: &'a str) -> impl Iterator<Item = &'a Session> + 'a {
+        self.sessions.iter().filter(move |s| s.song == song)
+    }
+
+    /// Bars of a song with their errors over all sessions, most errors first.
+    pub fn trouble_bars(&self, song: &str) -> Vec<(usize, u32)> {
+        let mut errors: HashMap<usize, u32> = HashMap::new();
+        for session in self.sessions(song) {
+            for &(bar, count) in session.bar_errors.iter() {
+                *errors.entry(bar).or_default() += count;
+            }
+        }
+        let mut bars: Vec<(usize, u32)> = errors.into_iter().collect();
+        bars.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        bars
+    }
+}
+
+/// Formats seconds since the Unix epoch as a `YYYY-MM-DD` date.
+pub fn format_date(secs: u64) -> String {
+    // civil from days, http://howardhinnant.github.io/date_algorithms.html
+    let z = (secs / 86_400) as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
@@ -1,54 +1,266 @@
-use midir::{MidiInput, MidiInputConnection};
+use std::thread;
+use std::time::Duration;
+
+use midir::{MidiInput, MidiInputConnection, MidiInputPort};
 use midly::{live::LiveEvent, MidiMessage};
 use winit::event_loop::EventLoopProxy;
 
 use crate::WhstlrsEvent;
 
+/// How often the ports are checked for devices that come and go.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Picks a MIDI input port by its index or by a part of its name.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PortSelector {
+    Index(usize),
+    /// Case insensitive part of the port name.
+    Name(String),
+}
+
+impl PortSelector {
+    /// A number selects by index, anything else by name.
+    pub fn parse(text: &str) -> Self {
+        match text.trim().parse() {
+            Ok(index) => PortSelector::Index(index),
+            Err(_) => PortSelector::Name(text.trim().to_string()),
+        }
+    }
+
+    pub fn find(&self, names: &[String]) -> Option<usize> {
+        match self {
+            PortSelector::Index(index) => (*index < names.len()).then_some(*index),
+            PortSelector::Name(name) => {
+                let name = name.to_lowercase();
+                names.iter().position(|n| n.to_lowercase().contains(&name))
+            }
+        }
+    }
+}
+
 pub struct InputConnection {
     tx: EventLoopProxy<WhstlrsEvent>,
     conn_in: Option<MidiInputConnection<()>>,
+    /// Name of the device in use, kept when it is unplugged to reconnect to it.
+    port_name: Option<String>,
+    selector: Option<PortSelector>,
+    /// Name of the last used device, connected to when there is no selector.
+    last_port: Option<String>,
+    /// Port names seen at the last change.
+    last_names: Vec<String>,
 }
 
 impl InputConnection {
+    /// Starts watching the ports for devices that come and go, see `ports_changed`.
     pub fn new(tx: EventLoopProxy<WhstlrsEvent>) -> Self {
-        InputConnection { tx, conn_in: None }
+        watch_ports(tx.clone());
+        InputConnection {
+            tx,
+            conn_in: None,
+            port_name: None,
+            selector: None,
+            last_port: None,
+            last_names: Vec::new(),
+        }
+    }
+
+    /// Names of the MIDI input ports, in the order of their index.
+    pub fn port_names() -> Result<Vec<String>, String> {
+        let midi_in = Self::midi_input()?;
+        Ok(Self::names(&midi_in, &midi_in.ports()))
+    }
+
+    /// Name of the connected port.
+    pub fn port_name(&self) -> Option<&str> {
+        self.conn_in.as_ref().and(self.port_name.as_deref())
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.conn_in.is_some()
+    }
+
+    fn midi_input() -> Result<MidiInput, String> {
+        MidiInput::new("Whstlrs").map_err(|e| format!("Could not open MIDI input: {}", e))
+    }
+
+    fn names(midi_in: &MidiInput, ports: &[MidiInputPort]) -> Vec<String> {
+        ports
+            .iter()
+            .map(|p| midi_in.port_name(p).unwrap_or_default())
+            .collect()
     }
 
-    pub fn connect_input(&mut self) {
+    /// Connects to the port picked by `selector`. Without one it goes back to
+    /// `last_port`, the last used device, or else the first port that isn't a
+    /// MIDI through port. Returns the name of the connected port.
+    pub fn connect_input(
+        &mut self,
+        selector: Option<&PortSelector>,
+        last_port: Option<&str>,
+    ) -> Result<String, String> {
+        self.selector = selector.cloned();
+        self.last_port = last_port.map(String::from);
+        self.port_name = None;
+        let names = Self::port_names()?;
+        for (i, name) in names.iter().enumerate() {
+            log::info!("MIDI input {}: {}", i, name);
+        }
+        self.last_names = names;
+        self.connect()
+    }
+
+    fn connect(&mut self) -> Result<String, String> {
+        self.conn_in = None;
+
+        let midi_in = Self::midi_input()?;
+        let ports = midi_in.ports();
+        let names = Self::names(&midi_in, &ports);
+
+        let index = match &self.port_name {
+            // back to the device that was unplugged, its index may have changed
+            Some(name) => names.iter().position(|n| same_device(n, name)),
+            None => self.first_port(&names),
+        }
+        .ok_or(String::from("No MIDI input device found"))?;
+
         let tx = self.tx.clone();
-        let midi_in = MidiInput::new("My Test Input").unwrap();
-        let in_ports = midi_in.ports();
-        let in_port = &in_ports[1];
         let conn_in = midi_in
             .connect(
-                &in_port,
-                "MidiIo-in-conn",
-                move |_, message, _| {
-                    let event = LiveEvent::parse(message).unwrap();
-
-                    if let LiveEvent::Midi { channel, message } = event {
-                        match message {
-                            // Some keyboards send NoteOn event with vel 0 instead of NoteOff
-                            midly::MidiMessage::NoteOn { key, vel } if vel == 0 => {
-                                tx.send_event(WhstlrsEvent::MidiInput {
-                                    channel: channel.as_int(),
-                                    message: MidiMessage::NoteOff { key, vel },
-                                })
-                                .ok();
-                            }
-                            message => {
-                                tx.send_event(WhstlrsEvent::MidiInput {
-                                    channel: channel.as_int(),
-                                    message,
-                                })
-                                .ok();
-                            }
-                        }
-                    }
-                },
+                &ports[index],
+                "whstlrs-in",
+                move |_, message, _| forward(&tx, message),
                 (),
             )
-            .unwrap();
+            .map_err(|e| format!("Could not connect to {}: {}", names[index], e))?;
+
+        let name = names[index].clone();
+        log::info!("Connected to MIDI input {}", name);
         self.conn_in = Some(conn_in);
+        self.port_name = Some(name.clone());
+        self.tx
+            .send_event(WhstlrsEvent::DeviceConnected { name: name.clone() })
+            .ok();
+        Ok(name)
+    }
+
+    /// The port to connect to at first: the one picked by the selector, or
+    /// else the last used device or the first port that isn't a MIDI through
+    /// port.
+    fn first_port(&self, names: &[String]) -> Option<usize> {
+        match &self.selector {
+            Some(selector) => selector.find(names).or_else(|| {
+                log::warn!("MIDI input {:?} not found", selector);
+                None
+            }),
+            None => None,
+        }
+        .or_else(|| {
+            let last = self.last_port.as_ref()?;
+            names.iter().position(|n| same_device(n, last))
+        })
+        .or_else(|| names.iter().position(|n| !n.contains("Through")))
+    }
+
+    /// Notices when the device is unplugged and reconnects when it comes
+    /// back, `names` are the ports after a change. Returns the name of the
+    /// port when it connected.
+    pub fn ports_changed(&mut self, names: Vec<String>) -> Option<String> {
+        if names == self.last_names {
+            return None;
+        }
+        self.last_names = names;
+
+        match (&self.conn_in, &self.port_name) {
+            (Some(_), Some(name)) => {
+                if !self.last_names.iter().any(|n| same_device(n, name)) {
+                    // the connection doesn't notice, it just stops receiving
+                    self.conn_in = None;
+                    log::warn!("MIDI input {} disconnected", name);
+                    self.tx
+                        .send_event(WhstlrsEvent::DeviceDisconnected { name: name.clone() })
+                        .ok();
+                }
+                None
+            }
+            (_, port_name) => {
+                // back to the unplugged device, or any device when there was none
+                let wanted = port_name
+                    .as_ref()
+                    .is_none_or(|name| self.last_names.iter().any(|n| same_device(n, name)));
+                if !wanted {
+                    return None;
+                }
+                self.connect().map_err(|e| log::debug!("{}", e)).ok()
+            }
+        }
+    }
+}
+
+/// Checks the ports now and then on a thread of its own, with one MIDI client,
+/// and sends their names to the event loop when they change.
+fn watch_ports(tx: EventLoopProxy<WhstlrsEvent>) {
+    let spawned = thread::Builder::new()
+        .name("midi-ports".to_string())
+        .spawn(move || {
+            let midi_in = match InputConnection::midi_input() {
+                Ok(midi_in) => midi_in,
+                Err(e) => {
+                    log::warn!("{}", e);
+                    return;
+                }
+            };
+            let mut last_names = InputConnection::names(&midi_in, &midi_in.ports());
+            loop {
+                thread::sleep(POLL_INTERVAL);
+                let names = InputConnection::names(&midi_in, &midi_in.ports());
+                if names == last_names {
+                    continue;
+                }
+                last_names = names.clone();
+                if tx
+                    .send_event(WhstlrsEvent::MidiPortsChanged { names })
+                    .is_err()
+                {
+                    // the event loop is gone
+                    return;
+                }
+            }
+        });
+    if let Err(e) = spawned {
+        log::warn!("Could not watch the MIDI ports: {}", e);
+    }
+}
+
+/// Sends the channel messages of the device to the event loop.
+fn forward(tx: &EventLoopProxy<WhstlrsEvent>, message: &[u8]) {
+    let Ok(LiveEvent::Midi { channel, message }) = LiveEvent::parse(message) else {
+        return;
+    };
+    let message = match message {
+        // Some keyboards send NoteOn event with vel 0 instead of NoteOff
+        MidiMessage::NoteOn { key, vel } if vel == 0 => MidiMessage::NoteOff { key, vel },
+        message => message,
+    };
+    tx.send_event(WhstlrsEvent::MidiInput {
+        channel: channel.as_int(),
+        message,
+    })
+    .ok();
+}
+
+/// Port names are the same device, ALSA adds a client and port number that
+/// can change when the device is plugged in again.
+fn same_device(a: &str, b: &str) -> bool {
+    fn device(name: &str) -> &str {
+        match name.rsplit_once(' ') {
+            Some((device, address))
+                if address.contains(':')
+                    && address.chars().all(|c| c.is_ascii_digit() || c == ':') =>
+            {
+                device
+            }
+            _ => name,
+        }
     }
+    device(a) == device(b)
 }
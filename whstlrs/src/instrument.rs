@@ -0,0 +1,365 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+use crate::song::SongFile;
+use crate::utils::dirs;
+use crate::whistle::WhistleKey;
+
+/// Directory in the data directory with fingering charts of more instruments.
+const CHARTS_DIR: &str = "fingerings";
+
+/// Fingering charts that come with the app.
+const BUILT_IN: [(&str, &str); 4] = [
+    (
+        "tinwhistle.csv",
+        include_str!("../../contrib/fingerings/tinwhistle.csv"),
+    ),
+    (
+        "lowwhistle.csv",
+        include_str!("../../contrib/fingerings/lowwhistle.csv"),
+    ),
+    (
+        "irishflute.csv",
+        include_str!("../../contrib/fingerings/irishflute.csv"),
+    ),
+    (
+        "recorder.csv",
+        include_str!("../../contrib/fingerings/recorder.csv"),
+    ),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HoleState {
+    Open,
+    Closed,
+    Half,
+}
+
+/// The holes to cover for a note, and how hard to blow.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fingering {
+    /// From the top hole down.
+    pub holes: Vec<HoleState>,
+    /// 0 blown softly, 1 overblown to the second octave and so on.
+    pub register: u8,
+}
+
+impl Fingering {
+    fn parse(holes: &str, register: &str) -> Result<Self, String> {
+        let holes = holes
+            .trim()
+            .chars()
+            .map(|c| match c {
+                'x' | 'X' => Ok(HoleState::Closed),
+                'o' | 'O' => Ok(HoleState::Open),
+                'h' | 'H' => Ok(HoleState::Half),
+                _ => Err(format!("Invalid hole {:?}", c)),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let register = register
+            .trim()
+            .parse()
+            .map_err(|_| format!("Invalid register {:?}", register))?;
+        Ok(Self { holes, register })
+    }
+
+    /// The closed holes as bits, the top hole highest, with the register above
+    /// them. `None` when a hole is half covered.
+    pub fn mask(&self) -> Option<u16> {
+        let mut mask = (self.register as u16) << self.holes.len();
+        for (i, hole) in self.holes.iter().enumerate() {
+            match hole {
+                HoleState::Closed => mask |= 1 << (self.holes.len() - 1 - i),
+                HoleState::Open => {}
+                HoleState::Half => return None,
+            }
+        }
+        Some(mask)
+    }
+}
+
+/// A fingering chart, loaded from a CSV file like those in `contrib/fingerings`.
+#[derive(Debug, Clone)]
+pub struct Instrument {
+    pub name: String,
+    pub holes: usize,
+    /// The lowest note, with all holes closed.
+    pub base: u8,
+    /// Fingerings of the written notes, the preferred one first.
+    fingerings: BTreeMap<u8, Vec<Fingering>>,
+}
+
+impl Default for Instrument {
+    /// The tin whistle in D.
+    fn default() -> Self {
+        let (source, text) = BUILT_IN[0];
+        Self::from_str(text, source).expect("built-in fingering chart")
+    }
+}
+
+impl Instrument {
+    /// The built-in instruments, and those in the data directory. A chart in
+    /// the data directory replaces the built-in one with the same name.
+    pub fn all() -> Vec<Instrument> {
+        let mut instruments: Vec<Instrument> = BUILT_IN
+            .iter()
+            .filter_map(|(source, text)| Self::from_str(text, source).ok())
+            .collect();
+        let dir = dirs::data_dir().join(CHARTS_DIR);
+        let Ok(entries) = fs::read_dir(&dir) else {
+            return instruments;
+        };
+        let mut paths: Vec<_> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "csv"))
+            .collect();
+        paths.sort();
+        for path in paths {
+            match Self::load(&path) {
+                Ok(instrument) => {
+                    instruments.retain(|i| !i.name.eq_ignore_ascii_case(&instrument.name));
+                    instruments.push(instrument);
+                }
+                Err(e) => log::warn!("{}", e),
+            }
+        }
+        instruments
+    }
+
+    /// The instrument by a case insensitive part of its name.
+    pub fn find(name: &str) -> Option<Instrument> {
+        let name = name.to_lowercase();
+        Self::all()
+            .into_iter()
+            .find(|i| i.name.to_lowercase().contains(&name))
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        Self::from_str(&text, &path.display().to_string())
+    }
+
+    /// Parses a chart, `source` names it in the errors.
+    pub fn from_str(text: &str, source: &str) -> Result<Self, String> {
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .comment(Some(b'#'))
+            .trim(csv::Trim::All)
+            .from_reader(text.as_bytes());
+        let mut name = None;
+        let mut holes = None;
+        let mut base = None;
+        let mut fingerings: BTreeMap<u8, Vec<Fingering>> = BTreeMap::new();
+        for record in reader.records() {
+            let record = record.map_err(|e| format!("{}: {}", source, e))?;
+            let line = record.position().map_or(0, |p| p.line());
+            let error = |e: String| format!("{} line {}: {}", source, line, e);
+            let field = |i: usize| {
+                record
+                    .get(i)
+                    .ok_or(error(format!("Missing field {}", i + 1)))
+            };
+            match field(0)? {
+                "instrument" => name = Some(field(1)?.to_string()),
+                "holes" => {
+                    let count = field(1)?
+                        .parse()
+                        .map_err(|_| error("Invalid holes".into()))?;
+                    holes = Some(count);
+                }
+                "base" => base = Some(parse_note(field(1)?).ok_or(error("Invalid base".into()))?),
+                note => {
+                    let key = parse_note(note).ok_or(error(format!("Invalid note {:?}", note)))?;
+                    let fingering =
+                        Fingering::parse(field(1)?, record.get(2).unwrap_or("0")).map_err(error)?;
+                    fingerings.entry(key).or_default().push(fingering);
+                }
+            }
+        }
+        let name = name.ok_or(format!("{}: No instrument name", source))?;
+        let holes = holes.ok_or(format!("{}: No hole count", source))?;
+        if let Some((&key, _)) = fingerings
+            .iter()
+            .find(|(_, fingerings)| fingerings.iter().any(|f| f.holes.len() != holes))
+        {
+            return Err(format!("{}: Note {} needs {} holes", source, key, holes));
+        }
+        let base = base
+            .or_else(|| fingerings.keys().next().copied())
+            .ok_or(format!("{}: No fingerings", source))?;
+        Ok(Self {
+            name,
+            holes,
+            base,
+            fingerings,
+        })
+    }
+
+    /// The written notes the instrument in `key` can play.
+    pub fn range(&self, key: WhistleKey) -> RangeInclusive<u8> {
+        let lowest = self.fingerings.keys().next().copied().unwrap_or(self.base);
+        let highest = self.fingerings.keys().last().copied().unwrap_or(self.base);
+        transposed(lowest, self.shift(key))..=transposed(highest, self.shift(key))
+    }
+
+    /// Fingerings of a note on the instrument in `key`, the preferred one
+    /// first. Empty when the instrument can't play it.
+    pub fn fingerings(&self, midi_key: u8, key: WhistleKey) -> &[Fingering] {
+        let Some(chart_key) = midi_key.checked_add_signed(-self.shift(key)) else {
+            return &[];
+        };
+        self.fingerings.get(&chart_key).map_or(&[], Vec::as_slice)
+    }
+
+    /// The lowest note played with the fingering in `mask`, see `Fingering::mask`.
+    pub fn note_of(&self, mask: u16, key: WhistleKey) -> Option<u8> {
+        self.fingerings
+            .iter()
+            .find(|(_, fingerings)| fingerings.iter().any(|f| f.mask() == Some(mask)))
+            .map(|(&chart_key, _)| transposed(chart_key, self.shift(key)))
+    }
+
+    /// Semitones the chart moves for `key`. The charts of instruments in D,
+    /// like the whistles, follow the key, others like the recorder don't.
+    fn shift(&self, key: WhistleKey) -> i8 {
+        if self.base % 12 == WhistleKey::D.bottom() % 12 {
+            key.transpose()
+        } else {
+            0
+        }
+    }
+
+    /// The transposition in semitones that fits the song on the instrument in
+    /// `key`: the fewest notes it can't play, then the smallest move, whole
+    /// octaves before other keys.
+    pub fn fit(&self, song: &SongFile, key: WhistleKey) -> i8 {
+        let keys = song.notes.iter().map(|note| note.midi_key as i32);
+        let (Some(lowest), Some(highest)) = (keys.clone().min(), keys.max()) else {
+            return 0;
+        };
+        let range = self.range(key);
+        let mut candidates: Vec<i32> =
+            (*range.start() as i32 - lowest..=*range.end() as i32 - highest).collect();
+        if candidates.is_empty() {
+            // the song doesn't fit, get as much as possible of it in range
+            candidates = (-24..=24).collect();
+        }
+        let unplayable = |shift: i32| {
+            song.notes
+                .iter()
+                .filter(|note| {
+                    u8::try_from(note.midi_key as i32 + shift)
+                        .map_or(true, |midi_key| self.fingerings(midi_key, key).is_empty())
+                })
+                .count()
+        };
+        candidates
+            .into_iter()
+            .filter(|shift| (-48..=48).contains(shift))
+            .min_by_key(|&shift| (unplayable(shift), shift % 12 != 0, shift.abs()))
+            .unwrap_or(0) as i8
+    }
+}
+
+fn transposed(key: u8, semitones: i8) -> u8 {
+    key.saturating_add_signed(semitones)
+}
+
+/// The MIDI key of a note name like "D4", "F#5" or "Bb3".
+pub fn parse_note(text: &str) -> Option<u8> {
+    let mut chars = text.trim().chars();
+    let pitch_class: i32 = match chars.next()?.to_ascii_uppercase() {
+        'C' => 0,
+        'D' => 2,
+        'E' => 4,
+        'F' => 5,
+        'G' => 7,
+        'A' => 9,
+        'B' => 11,
+        _ => return None,
+    };
+    let rest = chars.as_str();
+    let (alter, octave) = match rest.chars().next()? {
+        '#' => (1, &rest[1..]),
+        'b' => (-1, &rest[1..]),
+        _ => (0, rest),
+    };
+    let octave: i32 = octave.parse().ok()?;
+    u8::try_from((octave + 1) * 12 + pitch_class + alter)
+        .ok()
+        .filter(|&key| key < 128)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A built-in chart, without the ones in the data directory.
+    fn chart(name: &str) -> Instrument {
+        BUILT_IN
+            .iter()
+            .map(|(source, text)| Instrument::from_str(text, source).unwrap())
+            .find(|i| i.name == name)
+            .unwrap()
+    }
+
+    #[test]
+    fn whistle_charts_follow_the_key() {
+        let whistle = Instrument::default();
+        assert_eq!(whistle.range(WhistleKey::D).start(), &62);
+        assert_eq!(whistle.range(WhistleKey::C).start(), &60);
+        assert_eq!(
+            whistle.fingerings(60, WhistleKey::C),
+            whistle.fingerings(62, WhistleKey::D)
+        );
+        assert_eq!(whistle.note_of(0b111111, WhistleKey::Bb), Some(58));
+    }
+
+    fn song(keys: &[u8]) -> SongFile {
+        let notes = keys
+            .iter()
+            .enumerate()
+            .map(|(i, &midi_key)| crate::song::SongNote {
+                timestamp: i as f32,
+                midi_key,
+                duration: 4,
+                duration_length: 1.0,
+                notehead_id: String::new(),
+            })
+            .collect();
+        SongFile::from_notes(String::new(), Default::default(), notes)
+    }
+
+    #[test]
+    fn fit_prefers_whole_octaves() {
+        let whistle = Instrument::default();
+        // D major an octave below the whistle
+        assert_eq!(whistle.fit(&song(&[50, 54, 57, 62]), WhistleKey::D), 12);
+        // already in range
+        assert_eq!(whistle.fit(&song(&[62, 66, 69, 74]), WhistleKey::D), 0);
+        // C major fits the C whistle as it is, the D whistle plays it an
+        // octave up with the cross fingered C natural
+        let c_major = song(&[60, 64, 67, 72]);
+        assert_eq!(whistle.fit(&c_major, WhistleKey::C), 0);
+        assert_eq!(whistle.fit(&c_major, WhistleKey::D), 12);
+        // too wide for any transposition, as much as possible in range
+        let wide = song(&[40, 62, 66, 69, 74, 100]);
+        assert_eq!(whistle.fit(&wide, WhistleKey::D), 0);
+        assert_eq!(whistle.fit(&song(&[]), WhistleKey::D), 0);
+    }
+
+    #[test]
+    fn recorder_ignores_the_whistle_key() {
+        let recorder = chart("Recorder");
+        assert_eq!(
+            recorder.range(WhistleKey::Eb),
+            recorder.range(WhistleKey::D)
+        );
+        assert!(!recorder.fingerings(60, WhistleKey::Bb).is_empty());
+    }
+}
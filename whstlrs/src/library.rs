@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::song::{SongFile, SongInfo};
+use crate::utils::dirs;
+
+/// Directory in the data directory with the songs.
+const SONGS_DIR: &str = "songs";
+/// Directory with the songs that come with the app.
+const CONTRIB_DIR: &str = "contrib";
+/// Song formats, the first one is preferred when a song comes in several.
+const EXTENSIONS: [&str; 4] = ["notes", "abc", "mid", "midi"];
+
+/// A song found in the library.
+#[derive(Debug, Clone)]
+pub struct LibraryEntry {
+    pub path: PathBuf,
+    pub info: SongInfo,
+    /// The SVG sheet music that comes with the song.
+    pub has_sheet: bool,
+}
+
+impl LibraryEntry {
+    /// The title of the song, or else its file name.
+    pub fn title(&self) -> String {
+        self.info.title.clone().unwrap_or_else(|| {
+            self.path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+                .unwrap_or_default()
+        })
+    }
+
+    /// Every word of `filter` is in the title, key, rhythm or file name.
+    pub fn matches(&self, filter: &str) -> bool {
+        let text = format!(
+            "{} {} {} {}",
+            self.title(),
+            self.info.key.as_deref().unwrap_or_default(),
+            self.info.rhythm.as_deref().unwrap_or_default(),
+            self.path.file_name().unwrap_or_default().to_string_lossy()
+        )
+        .to_lowercase();
+        filter
+            .to_lowercase()
+            .split_whitespace()
+            .all(|word| text.contains(word))
+    }
+}
+
+/// The songs in a directory and the directories in it.
+pub struct Library {
+    pub dir: PathBuf,
+    pub entries: Vec<LibraryEntry>,
+}
+
+impl Library {
+    /// The songs directory in the data directory when there is one, or else
+    /// the `contrib` directory next to the app or the working directory, or
+    /// a few levels up as in the source tree. The songs directory, that may
+    /// be missing, when there is neither.
+    pub fn default_dir() -> PathBuf {
+        let songs = dirs::data_dir().join(SONGS_DIR);
+        if songs.is_dir() {
+            return songs;
+        }
+        let app_dir = env::current_exe()
+            .ok()
+            .and_then(|exe| exe.parent().map(Path::to_path_buf));
+        app_dir
+            .into_iter()
+            .chain(env::current_dir().ok())
+            .flat_map(|dir| {
+                dir.ancestors()
+                    .take(3)
+                    .map(|dir| dir.join(CONTRIB_DIR))
+                    .collect::<Vec<_>>()
+            })
+            .find(|dir| dir.is_dir())
+            .unwrap_or(songs)
+    }
+
+    /// Finds the songs in `dir`. A song in several formats, like the `.notes`
+    /// and `.abc` file of a LilyPond export, is listed once. Songs that can't
+    /// be read are left out.
+    pub fn scan<P: AsRef<Path>>(dir: P) -> Result<Self, String> {
+        let dir = dir.as_ref().to_path_buf();
+        let mut paths = Vec::new();
+        find_songs(&dir, &mut paths)
+            .map_err(|e| format!("Could not read {}: {}", dir.display(), e))?;
+
+        // the formats of each song, the preferred one first
+        let mut songs: HashMap<PathBuf, Vec<PathBuf>> = HashMap::new();
+        for path in paths {
+            songs.entry(path.with_extension("")).or_default().push(path);
+        }
+
+        let mut entries: Vec<LibraryEntry> = songs
+            .into_values()
+            .filter_map(|mut formats| {
+                formats.sort_by_key(|path| rank(path));
+                entry(&formats)
+            })
+            .collect();
+        entries.sort_by_cached_key(|entry| (entry.title().to_lowercase(), entry.path.clone()));
+        log::info!("{} songs in {}", entries.len(), dir.display());
+        Ok(Self { dir, entries })
+    }
+
+    /// Indices of the entries that match `filter`, see `LibraryEntry::matches`.
+    pub fn filter(&self, filter: &str) -> Vec<usize> {
+        (0..self.entries.len())
+            .filter(|&i| self.entries[i].matches(filter))
+            .collect()
+    }
+}
+
+/// The entry of the song in the preferred format. The details it lacks, like
+/// the title that a `.notes` file doesn't have, come from the other formats.
+fn entry(formats: &[PathBuf]) -> Option<LibraryEntry> {
+    let mut entry: Option<LibraryEntry> = None;
+    for path in formats {
+        let info = match SongFile::new(path) {
+            Ok(song) => song.info,
+            Err(e) => {
+                log::warn!("{}: {}", path.display(), e);
+                continue;
+            }
+        };
+        match &mut entry {
+            None => {
+                entry = Some(LibraryEntry {
+                    path: path.clone(),
+                    info,
+                    has_sheet: path.with_extension("svg").is_file(),
+                })
+            }
+            Some(entry) => {
+                let known = &mut entry.info;
+                known.title = known.title.take().or(info.title);
+                known.key = known.key.take().or(info.key);
+                known.meter = known.meter.or(info.meter);
+                known.rhythm = known.rhythm.take().or(info.rhythm);
+            }
+        }
+    }
+    entry
+}
+
+fn rank(path: &Path) -> usize {
+    let ext = path.extension().unwrap_or_default().to_string_lossy();
+    EXTENSIONS
+        .iter()
+        .position(|e| ext.eq_ignore_ascii_case(e))
+        .unwrap_or(EXTENSIONS.len())
+}
+
+fn find_songs(dir: &Path, paths: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            // a directory that can't be read doesn't spoil the rest
+            if let Err(e) = find_songs(&path, paths) {
+                log::warn!("Could not read {}: {}", path.display(), e);
+            }
+        } else if rank(&path) < EXTENSIONS.len() {
+            paths.push(path);
+        }
+    }
+    Ok(())
+}
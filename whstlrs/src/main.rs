@@ -1,15 +1,27 @@
+mod cli;
+mod config;
 mod context;
+mod export;
+mod history;
 mod input_manager;
+mod instrument;
+mod library;
 mod output_manager;
 mod render;
 mod scene;
 mod song;
+mod synth;
 mod utils;
+mod virtual_input;
+mod whistle;
 
+use crate::cli::AppOptions;
+use crate::config::Config;
 use crate::context::Context;
+use crate::song::Song;
 
 use midly::MidiMessage;
-use scene::{playing_scene, Scene};
+use scene::{playing_scene, stack::SceneStack, SceneRequest};
 use std::sync::Arc;
 use std::time::Duration;
 use utils::window::WindowState;
@@ -29,6 +41,23 @@ pub enum WhstlrsEvent {
         /// The MIDI message type and associated data.
         message: MidiMessage,
     },
+    /// A MIDI input device is connected, also after it was unplugged.
+    DeviceConnected {
+        name: String,
+    },
+    DeviceDisconnected {
+        name: String,
+    },
+    /// The MIDI input ports changed, with their names.
+    MidiPortsChanged {
+        names: Vec<String>,
+    },
+    /// Shows a scene on top of the current one.
+    PushScene(SceneRequest),
+    /// Goes back to the scene below the current one, or exits from the last one.
+    PopScene,
+    /// Shows a scene instead of the current one.
+    ReplaceScene(SceneRequest),
     Exit,
 }
 
@@ -36,14 +65,26 @@ struct Whstlrs {
     context: Context,
     surface: Surface,
     msaa_texture: Option<TextureView>,
-    game_scene: Box<dyn Scene>,
+    scenes: SceneStack,
     last_time: std::time::Instant,
 }
 
 impl Whstlrs {
-    fn new(mut context: Context, surface: Surface) -> Self {
-        let whistletab_scene =
-            playing_scene::PlayingScene::new(&context, context.song.clone().unwrap());
+    fn new(mut context: Context, surface: Surface, options: &AppOptions) -> Result<Self, String> {
+        // the song picker is below the song, to go back to it
+        let mut scenes = SceneStack::default();
+        let picker = SceneRequest::SongPicker.build(&mut context)?;
+        scenes.push(&mut context, picker);
+        if let Some(song) = context.song.clone() {
+            let mut playing = playing_scene::PlayingScene::new(&context, song)?;
+            if let Some(bpm) = options.bpm {
+                playing.set_bpm(bpm);
+            }
+            if options.transpose != 0 {
+                playing.transpose(&context, options.transpose, 0);
+            }
+            scenes.push(&mut context, Box::new(playing));
+        }
         context.resize();
         context.gpu.submit();
 
@@ -69,13 +110,13 @@ impl Whstlrs {
         );
         //msaa_texture = None;
 
-        Self {
+        Ok(Self {
             context,
             surface,
             msaa_texture,
-            game_scene: Box::new(whistletab_scene),
+            scenes,
             last_time: std::time::Instant::now(),
-        }
+        })
     }
 
     fn window_event(
@@ -132,7 +173,9 @@ impl Whstlrs {
                     },
                 ..
             } => match logical_key {
-                winit::keyboard::Key::Character(c) if c.as_str() == "f" => {
+                winit::keyboard::Key::Character(c)
+                    if c.as_str() == "f" && !self.scenes.wants_text_input() =>
+                {
                     if self.context.window.fullscreen().is_some() {
                         self.context.window.set_fullscreen(None);
                     } else {
@@ -147,7 +190,8 @@ impl Whstlrs {
                     }
                 }
                 winit::keyboard::Key::Named(winit::keyboard::NamedKey::Escape) => {
-                    event_loop.exit();
+                    self.pop_scene(event_loop);
+                    return;
                 }
 
                 _ => {}
@@ -164,7 +208,9 @@ impl Whstlrs {
             }
             _ => {}
         }
-        self.game_scene.window_event(&mut self.context, event);
+        if self.scenes.wants_text_input() || !self.context.virtual_input.window_event(event) {
+            self.scenes.window_event(&mut self.context, event);
+        }
     }
 
     fn whstlrs_event(
@@ -174,17 +220,42 @@ impl Whstlrs {
     ) {
         match event {
             WhstlrsEvent::MidiInput { channel, message } => {
-                self.game_scene
-                    .midi_event(&mut self.context, channel, &message);
+                self.scenes.midi_event(&mut self.context, channel, &message);
+            }
+            WhstlrsEvent::DeviceConnected { name } => {
+                self.scenes.device_event(&mut self.context, &name, true);
+            }
+            WhstlrsEvent::DeviceDisconnected { name } => {
+                self.scenes.device_event(&mut self.context, &name, false);
+            }
+            WhstlrsEvent::MidiPortsChanged { names } => {
+                self.context.ports_changed(names);
             }
+            WhstlrsEvent::PushScene(request) => match request.build(&mut self.context) {
+                Ok(scene) => self.scenes.push(&mut self.context, scene),
+                Err(e) => log::error!("{}", e),
+            },
+            WhstlrsEvent::PopScene => self.pop_scene(event_loop),
+            WhstlrsEvent::ReplaceScene(request) => match request.build(&mut self.context) {
+                Ok(scene) => self.scenes.replace(&mut self.context, scene),
+                Err(e) => log::error!("{}", e),
+            },
             WhstlrsEvent::Exit => {
                 event_loop.exit();
             }
         }
     }
 
+    /// Goes back to the scene below, the app exits when there is none.
+    fn pop_scene(&mut self, event_loop: &winit::event_loop::EventLoopWindowTarget<WhstlrsEvent>) {
+        self.scenes.pop(&mut self.context);
+        if self.scenes.is_empty() {
+            event_loop.exit();
+        }
+    }
+
     fn update(&mut self, delta: Duration) {
-        self.game_scene.update(&mut self.context, delta);
+        self.scenes.update(&mut self.context, delta);
     }
 
     fn render(&mut self) {
@@ -225,7 +296,7 @@ impl Whstlrs {
                         timestamp_writes: None,
                         occlusion_query_set: None,
                     });
-            self.game_scene.render(&self.context.transform, &mut rpass);
+            self.scenes.render(&self.context.transform, &mut rpass);
         }
 
         self.context.gpu.submit();
@@ -237,12 +308,35 @@ fn main() {
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("whstlrs=info"))
         .init();
 
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Some(result) = cli::run_command(&args) {
+        if let Err(e) = result {
+            log::error!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // the options of the command line go before the settings, for this run only
+    let saved_config = Config::load();
+    let mut config = saved_config.clone();
+    let options = cli::app_options(&args, &mut config).unwrap_or_else(|e| {
+        log::error!("{}", e);
+        std::process::exit(2);
+    });
+    let song = options.song.as_ref().map(|path| {
+        Song::load_with_selection(path, options.selection).unwrap_or_else(|e| {
+            log::error!("{}: {}", path.display(), e);
+            std::process::exit(1);
+        })
+    });
+
     let event_loop: EventLoop<WhstlrsEvent> = EventLoopBuilder::with_user_event().build().unwrap();
 
     let builder = winit::window::WindowBuilder::new()
         .with_inner_size(winit::dpi::LogicalSize {
-            width: 1280.0,
-            height: 720.0,
+            width: config.window_width as f64,
+            height: config.window_height as f64,
         })
         .with_title("Whistlrs")
         .with_theme(Some(winit::window::Theme::Dark));
@@ -256,9 +350,23 @@ fn main() {
         futures::executor::block_on(Gpu::for_window(window.clone(), size.width, size.height))
             .unwrap();
 
-    let ctx = Context::new(window, window_state, event_loop.create_proxy(), gpu);
+    let mut ctx = Context::new(
+        window,
+        window_state,
+        event_loop.create_proxy(),
+        gpu,
+        config,
+        saved_config,
+    );
+    ctx.song = song;
 
-    let mut app = Whstlrs::new(ctx, surface);
+    let mut app = match Whstlrs::new(ctx, surface, &options) {
+        Ok(app) => app,
+        Err(e) => {
+            log::error!("{}", e);
+            std::process::exit(1);
+        }
+    };
 
     event_loop
         .run(move |event, event_loop| {
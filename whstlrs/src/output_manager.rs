@@ -1,29 +1,178 @@
-use midir::{MidiOutput, MidiOutputConnection};
-use midly::{num::u4, MidiMessage};
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::rc::Rc;
 
+use midir::{MidiOutput, MidiOutputConnection, MidiOutputPort};
+use midly::{
+    live::LiveEvent,
+    num::{u4, u7},
+    MidiMessage,
+};
+
+use crate::input_manager::PortSelector;
+use crate::synth::AudioOutput;
+
+/// General MIDI programs counted from zero, as they are sent. The GM list
+/// counts from one, so these are Flute 74 and Whistle 79 there.
+pub const PROGRAM_FLUTE: u8 = 73;
+pub const PROGRAM_WHISTLE: u8 = 78;
+/// The General MIDI percussion channel, it keeps its drum kit.
+pub const DRUM_CHANNEL: u8 = 9;
+/// General MIDI high and low wood block, for the clicks on the beat.
+pub const CLICK_ACCENT: u8 = 76;
+pub const CLICK: u8 = 77;
+
+/// Name of the built-in synthesizer, as an output port.
+pub const SYNTH_NAME: &str = "Built-in synth";
+
+enum Sink {
+    None,
+    Midi(MidiOutputConnection),
+    Synth(AudioOutput),
+}
+
+struct Output {
+    sink: Sink,
+    port_name: Option<String>,
+    program: u8,
+    /// Channels that got the program change.
+    programmed: HashSet<u8>,
+}
+
+/// Sends the song to a MIDI output port or the built-in synthesizer. Clones
+/// share the connection, sending without one does nothing.
+#[derive(Clone)]
 pub struct OutputConnection {
-    conn_out: MidiOutputConnection,
+    output: Rc<RefCell<Output>>,
 }
 
 impl OutputConnection {
     pub fn new() -> Self {
-        let midi_out = MidiOutput::new("Whstlrs").unwrap();
-        let out_ports = midi_out.ports();
-        for (i, p) in out_ports.iter().enumerate() {
-            log::info!("{}: {}", i, midi_out.port_name(p).unwrap());
+        OutputConnection {
+            output: Rc::new(RefCell::new(Output {
+                sink: Sink::None,
+                port_name: None,
+                program: PROGRAM_WHISTLE,
+                programmed: HashSet::new(),
+            })),
+        }
+    }
+
+    /// Names of the MIDI output ports, in the order of their index.
+    pub fn port_names() -> Result<Vec<String>, String> {
+        let midi_out = Self::midi_output()?;
+        Ok(Self::names(&midi_out, &midi_out.ports()))
+    }
+
+    /// Name of the connected port.
+    pub fn port_name(&self) -> Option<String> {
+        self.output.borrow().port_name.clone()
+    }
+
+    fn midi_output() -> Result<MidiOutput, String> {
+        MidiOutput::new("Whstlrs").map_err(|e| format!("Could not open MIDI output: {}", e))
+    }
+
+    fn names(midi_out: &MidiOutput, ports: &[MidiOutputPort]) -> Vec<String> {
+        ports
+            .iter()
+            .map(|p| midi_out.port_name(p).unwrap_or_default())
+            .collect()
+    }
+
+    /// Connects to the port picked by `selector`, or else the first port that
+    /// isn't a MIDI through port. Returns the name of the connected port.
+    pub fn connect(&mut self, selector: Option<&PortSelector>) -> Result<String, String> {
+        self.disconnect();
+
+        let midi_out = Self::midi_output()?;
+        let ports = midi_out.ports();
+        let names = Self::names(&midi_out, &ports);
+        for (i, name) in names.iter().enumerate() {
+            log::info!("MIDI output {}: {}", i, name);
         }
-        let out_port = &out_ports[0];
-        let conn_out = midi_out.connect(out_port, "whstlrs-out").unwrap();
-        OutputConnection { conn_out }
-    }
-    pub fn midi_event(&mut self, _channel: u4, msg: MidiMessage) {
-        let (msg, key, velocity) = match msg {
-            MidiMessage::NoteOn { vel, key } => (0x90, key.as_int(), vel.as_int()),
-            MidiMessage::NoteOff { vel, key } => (0x80, key.as_int(), vel.as_int()),
-            _ => {
-                return;
-            }
+
+        let index = match selector {
+            Some(selector) => selector
+                .find(&names)
+                .ok_or(format!("MIDI output {:?} not found", selector))?,
+            None => names
+                .iter()
+                .position(|n| !n.contains("Through"))
+                .ok_or(String::from("No MIDI output device found"))?,
+        };
+        let conn_out = midi_out
+            .connect(&ports[index], "whstlrs-out")
+            .map_err(|e| format!("Could not connect to {}: {}", names[index], e))?;
+
+        log::info!("Connected to MIDI output {}", names[index]);
+        let mut output = self.output.borrow_mut();
+        output.sink = Sink::Midi(conn_out);
+        output.port_name = Some(names[index].clone());
+        output.programmed.clear();
+        Ok(names[index].clone())
+    }
+
+    /// Plays on the built-in synthesizer instead of a MIDI port.
+    pub fn connect_synth(&mut self) -> Result<(), String> {
+        self.disconnect();
+        let audio = AudioOutput::new()?;
+        let mut output = self.output.borrow_mut();
+        output.sink = Sink::Synth(audio);
+        output.port_name = Some(SYNTH_NAME.to_string());
+        Ok(())
+    }
+
+    pub fn disconnect(&mut self) {
+        let mut output = self.output.borrow_mut();
+        output.sink = Sink::None;
+        output.port_name = None;
+    }
+
+    pub fn program(&self) -> u8 {
+        self.output.borrow().program
+    }
+
+    /// Picks the General MIDI program for the song, counted from zero.
+    pub fn set_program(&mut self, program: u8) {
+        let mut output = self.output.borrow_mut();
+        output.program = program.min(127);
+        // sent again before the next message on each channel
+        output.programmed.clear();
+    }
+
+    pub fn midi_event(&mut self, channel: u4, msg: MidiMessage) {
+        let mut output = self.output.borrow_mut();
+        if matches!(output.sink, Sink::None) {
+            return;
+        }
+        if channel.as_int() != DRUM_CHANNEL
+            && !matches!(msg, MidiMessage::ProgramChange { .. })
+            && output.programmed.insert(channel.as_int())
+        {
+            let program = MidiMessage::ProgramChange {
+                program: u7::new(output.program),
+            };
+            output.send(channel, program);
+        }
+        output.send(channel, msg);
+    }
+}
+
+impl Output {
+    fn send(&mut self, channel: u4, message: MidiMessage) {
+        let conn_out = match &mut self.sink {
+            Sink::Midi(conn_out) => conn_out,
+            Sink::Synth(audio) => return audio.midi_event(channel.as_int(), &message),
+            Sink::None => return,
         };
-        // DEBUG self.conn_out.send(&[msg, key, velocity]).unwrap();
+        let mut buf = Vec::with_capacity(3);
+        let event = LiveEvent::Midi { channel, message };
+        if event.write_std(&mut buf).is_err() {
+            return;
+        }
+        if let Err(e) = conn_out.send(&buf) {
+            log::error!("Could not send MIDI message: {}", e);
+        }
     }
 }
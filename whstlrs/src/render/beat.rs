@@ -0,0 +1,47 @@
+use resvg::usvg;
+use wgpu_jumpstart::{wgpu, Gpu, TransformUniform, Uniform};
+
+use super::sheet::pipeline::SheetPipeline;
+
+/// Id of the dot, a beat marker of the pipeline that is white on the white
+/// background until it is lit.
+const DOT_ID: &str = "beat-dot";
+
+/// A dot in the corner of the window that lights up on the beat.
+pub struct BeatRenderer {
+    pipeline: SheetPipeline,
+    lit: bool,
+}
+
+impl BeatRenderer {
+    pub fn new(gpu: &Gpu, transform_uniform: &Uniform<TransformUniform>) -> Result<Self, String> {
+        let svg = format!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 1280 720" width="1280" height="720"><g id="{DOT_ID}"><circle cx="1240" cy="40" r="16" fill="white"/></g></svg>"#
+        );
+        let fontdb = usvg::fontdb::Database::new();
+        let pipeline = SheetPipeline::from_svg(gpu, transform_uniform, svg.as_bytes(), &fontdb)?;
+        Ok(Self {
+            pipeline,
+            lit: false,
+        })
+    }
+
+    pub fn set_lit(&mut self, gpu: &mut Gpu, lit: bool) {
+        if lit == self.lit {
+            return;
+        }
+        self.lit = lit;
+        if let Some(dot) = self.pipeline.beat_states_mut().get_mut(DOT_ID) {
+            *dot = lit;
+        }
+        self.pipeline.update_time(gpu);
+    }
+
+    pub fn render<'rpass>(
+        &'rpass self,
+        transform_uniform: &'rpass Uniform<TransformUniform>,
+        render_pass: &mut wgpu::RenderPass<'rpass>,
+    ) {
+        self.pipeline.render(transform_uniform, render_pass);
+    }
+}
@@ -0,0 +1,156 @@
+use std::fmt::Write;
+
+use resvg::usvg;
+use wgpu_jumpstart::{wgpu, Gpu, TransformUniform, Uniform};
+
+use super::sheet::pipeline::SheetPipeline;
+use super::svg::text;
+use crate::library::Library;
+
+const WIDTH: f32 = 1280.0;
+const HEIGHT: f32 = 720.0;
+/// Number of songs shown at a time.
+const ROWS: usize = 14;
+const ROW_HEIGHT: f32 = 38.0;
+/// Longer titles are cut off, to stay clear of the next column.
+const TITLE_LENGTH: usize = 45;
+
+/// The list of songs in the library, with the filter and the selected song.
+pub struct LibraryRenderer {
+    pipeline: SheetPipeline,
+}
+
+impl LibraryRenderer {
+    pub const ROWS: usize = ROWS;
+
+    /// `shown` are the indices of the entries that pass the filter, `selected`
+    /// is a position in `shown`. `filter` is `None` when the list isn't filtered.
+    pub fn new(
+        gpu: &Gpu,
+        transform_uniform: &Uniform<TransformUniform>,
+        fontdb: &usvg::fontdb::Database,
+        library: &Library,
+        shown: &[usize],
+        filter: Option<&str>,
+        selected: usize,
+    ) -> Result<Self, String> {
+        let svg = library_svg(library, shown, filter, selected);
+        let pipeline = SheetPipeline::from_svg(gpu, transform_uniform, svg.as_bytes(), fontdb)?;
+        Ok(Self { pipeline })
+    }
+
+    pub fn render<'rpass>(
+        &'rpass self,
+        transform_uniform: &'rpass Uniform<TransformUniform>,
+        render_pass: &mut wgpu::RenderPass<'rpass>,
+    ) {
+        self.pipeline.render(transform_uniform, render_pass);
+    }
+}
+
+fn library_svg(
+    library: &Library,
+    shown: &[usize],
+    filter: Option<&str>,
+    selected: usize,
+) -> String {
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 {WIDTH} {HEIGHT}" width="{WIDTH}" height="{HEIGHT}">"#
+    );
+    let _ = writeln!(
+        svg,
+        r#"<rect x="0" y="0" width="{WIDTH}" height="{HEIGHT}" fill="white"/>"#
+    );
+    text(&mut svg, 40.0, 60.0, 32.0, "start", "Songs");
+    text(
+        &mut svg,
+        40.0,
+        92.0,
+        16.0,
+        "start",
+        &format!(
+            "{} of {} in {}",
+            shown.len(),
+            library.entries.len(),
+            library.dir.display()
+        ),
+    );
+    text(
+        &mut svg,
+        40.0,
+        132.0,
+        20.0,
+        "start",
+        &match filter {
+            Some(filter) => format!("Filter: {}_", filter),
+            None => "/ filters the list".to_string(),
+        },
+    );
+
+    let columns = [
+        ("Title", 40.0),
+        ("Key", 720.0),
+        ("Meter", 860.0),
+        ("Rhythm", 980.0),
+    ];
+    let top = 180.0;
+    for (name, x) in columns {
+        text(&mut svg, x, top, 16.0, "start", name);
+    }
+    let _ = writeln!(
+        svg,
+        r#"<path d="M 40 {} L {} {}" stroke="black" stroke-width="1"/>"#,
+        top + 8.0,
+        WIDTH - 40.0,
+        top + 8.0
+    );
+    if shown.is_empty() {
+        text(
+            &mut svg,
+            40.0,
+            top + ROW_HEIGHT,
+            20.0,
+            "start",
+            "No songs found",
+        );
+    }
+
+    // scroll to keep the selected song in view
+    let first = selected
+        .saturating_sub(ROWS / 2)
+        .min(shown.len().saturating_sub(ROWS));
+    for (row, &index) in shown.iter().enumerate().skip(first).take(ROWS) {
+        let entry = &library.entries[index];
+        let y = top + ROW_HEIGHT * (row - first + 1) as f32;
+        if row == selected {
+            let _ = writeln!(
+                svg,
+                r#"<rect x="30" y="{}" width="{}" height="{ROW_HEIGHT}" fill="rgb(200,220,255)"/>"#,
+                y - ROW_HEIGHT + 10.0,
+                WIDTH - 60.0
+            );
+        }
+        let mut title: String = entry.title().chars().take(TITLE_LENGTH).collect();
+        if !entry.has_sheet {
+            title.push_str(" (no sheet music)");
+        }
+        let meter = entry
+            .info
+            .meter
+            .map(|m| format!("{}/{}", m.numerator, m.denominator))
+            .unwrap_or_default();
+        let cells = [
+            title,
+            entry.info.key.clone().unwrap_or_default(),
+            meter,
+            entry.info.rhythm.clone().unwrap_or_default(),
+        ];
+        for ((_, x), cell) in columns.iter().zip(cells) {
+            text(&mut svg, *x, y, 20.0, "start", &cell);
+        }
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
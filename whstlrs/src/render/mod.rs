@@ -1,3 +1,14 @@
+mod beat;
+mod library;
+mod progress;
+mod settings;
 mod sheet;
+mod summary;
+mod svg;
 
-pub use sheet::SheetRenderer;
+pub use beat::BeatRenderer;
+pub use library::LibraryRenderer;
+pub use progress::ProgressRenderer;
+pub use settings::SettingsRenderer;
+pub use sheet::{render_png, SheetRenderer};
+pub use summary::SummaryRenderer;
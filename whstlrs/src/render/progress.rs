@@ -0,0 +1,188 @@
+use std::fmt::Write;
+
+use resvg::usvg;
+use wgpu_jumpstart::{wgpu, Gpu, TransformUniform, Uniform};
+
+use super::sheet::pipeline::SheetPipeline;
+use super::svg::text;
+use crate::history::{format_date, History};
+
+const WIDTH: f32 = 1280.0;
+const HEIGHT: f32 = 720.0;
+/// Number of the latest sessions in the accuracy chart.
+const SESSIONS: usize = 20;
+/// Number of bars in the list of bars that go wrong most.
+const TROUBLE_BARS: usize = 10;
+
+/// Charts the practice history of a song: the accuracy of the latest
+/// sessions and the bars that most often go wrong.
+pub struct ProgressRenderer {
+    pipeline: SheetPipeline,
+}
+
+impl ProgressRenderer {
+    pub fn new(
+        gpu: &Gpu,
+        transform_uniform: &Uniform<TransformUniform>,
+        history: &History,
+        song: &str,
+    ) -> Result<Self, String> {
+        let mut fontdb = usvg::fontdb::Database::new();
+        fontdb.load_system_fonts();
+        let svg = progress_svg(history, song);
+        let pipeline = SheetPipeline::from_svg(gpu, transform_uniform, svg.as_bytes(), &fontdb)?;
+        Ok(Self { pipeline })
+    }
+
+    pub fn render<'rpass>(
+        &'rpass self,
+        transform_uniform: &'rpass Uniform<TransformUniform>,
+        render_pass: &mut wgpu::RenderPass<'rpass>,
+    ) {
+        self.pipeline.render(transform_uniform, render_pass);
+    }
+}
+
+fn progress_svg(history: &History, song: &str) -> String {
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 {WIDTH} {HEIGHT}" width="{WIDTH}" height="{HEIGHT}">"#
+    );
+    let _ = writeln!(
+        svg,
+        r#"<rect x="0" y="0" width="{WIDTH}" height="{HEIGHT}" fill="white"/>"#
+    );
+    text(
+        &mut svg,
+        40.0,
+        60.0,
+        32.0,
+        "start",
+        &format!("Progress: {}", song),
+    );
+
+    let sessions: Vec<_> = history.sessions(song).collect();
+    let Some(last) = sessions.last() else {
+        text(
+            &mut svg,
+            WIDTH / 2.0,
+            HEIGHT / 2.0,
+            24.0,
+            "middle",
+            "No sessions of this song yet",
+        );
+        svg.push_str("</svg>\n");
+        return svg;
+    };
+    let best = sessions.iter().map(|s| s.accuracy).fold(0.0, f32::max);
+    text(
+        &mut svg,
+        40.0,
+        95.0,
+        18.0,
+        "start",
+        &format!(
+            "{} sessions, best {:.0}%, last {:.0}%{} on {}",
+            sessions.len(),
+            best * 100.0,
+            last.accuracy * 100.0,
+            last.bpm
+                .map(|bpm| format!(" at {:.0} bpm", bpm))
+                .unwrap_or_default(),
+            format_date(last.date)
+        ),
+    );
+
+    // accuracy of the latest sessions
+    let (left, top, width, height) = (60.0, 170.0, 700.0, 450.0);
+    let bottom = top + height;
+    text(&mut svg, 40.0, 145.0, 20.0, "start", "Accuracy per session");
+    let _ = writeln!(
+        svg,
+        r#"<path d="M {left} {top} L {left} {bottom} L {} {bottom}" fill="none" stroke="black" stroke-width="2"/>"#,
+        left + width
+    );
+    let latest = &sessions[sessions.len().saturating_sub(SESSIONS)..];
+    let slot = width / SESSIONS as f32;
+    for (i, session) in latest.iter().enumerate() {
+        let x = left + slot * i as f32;
+        let bar_height = (session.accuracy.clamp(0.0, 1.0) * height).max(1.0);
+        let _ = writeln!(
+            svg,
+            r#"<rect x="{}" y="{}" width="{}" height="{bar_height}" fill="rgb(0,160,0)"/>"#,
+            x + slot * 0.15,
+            bottom - bar_height,
+            slot * 0.7
+        );
+        let center = x + slot / 2.0;
+        text(
+            &mut svg,
+            center,
+            bottom - bar_height - 6.0,
+            12.0,
+            "middle",
+            &format!("{:.0}%", session.accuracy * 100.0),
+        );
+        // month and day
+        text(
+            &mut svg,
+            center,
+            bottom + 18.0,
+            11.0,
+            "middle",
+            &format_date(session.date)[5..],
+        );
+    }
+
+    // bars that go wrong most
+    let (left, top, width) = (820.0, 170.0, 300.0);
+    text(
+        &mut svg,
+        left,
+        145.0,
+        20.0,
+        "start",
+        "Bars that go wrong most",
+    );
+    let trouble = history.trouble_bars(song);
+    let max = trouble.first().map_or(1, |&(_, count)| count.max(1)) as f32;
+    if trouble.is_empty() {
+        text(
+            &mut svg,
+            left,
+            top + 24.0,
+            16.0,
+            "start",
+            "None, well done!",
+        );
+    }
+    for (i, &(bar, count)) in trouble.iter().take(TROUBLE_BARS).enumerate() {
+        let y = top + 40.0 * i as f32;
+        text(
+            &mut svg,
+            left,
+            y + 22.0,
+            16.0,
+            "start",
+            &format!("Bar {}", bar + 1),
+        );
+        let bar_width = count as f32 / max * width;
+        let _ = writeln!(
+            svg,
+            r#"<rect x="{}" y="{}" width="{bar_width}" height="28" fill="rgb(220,0,0)"/>"#,
+            left + 70.0,
+            y
+        );
+        text(
+            &mut svg,
+            left + 80.0 + bar_width,
+            y + 22.0,
+            16.0,
+            "start",
+            &count.to_string(),
+        );
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
@@ -0,0 +1,77 @@
+use std::fmt::Write;
+
+use resvg::usvg;
+use wgpu_jumpstart::{wgpu, Gpu, TransformUniform, Uniform};
+
+use super::sheet::pipeline::SheetPipeline;
+use super::svg::text;
+
+const WIDTH: f32 = 1280.0;
+const HEIGHT: f32 = 720.0;
+const ROW_HEIGHT: f32 = 40.0;
+
+/// The settings with their values, and the selected one.
+pub struct SettingsRenderer {
+    pipeline: SheetPipeline,
+}
+
+impl SettingsRenderer {
+    /// `rows` are the names and values of the settings, `selected` is an index in them.
+    pub fn new(
+        gpu: &Gpu,
+        transform_uniform: &Uniform<TransformUniform>,
+        fontdb: &usvg::fontdb::Database,
+        rows: &[(&str, String)],
+        selected: usize,
+    ) -> Result<Self, String> {
+        let svg = settings_svg(rows, selected);
+        let pipeline = SheetPipeline::from_svg(gpu, transform_uniform, svg.as_bytes(), fontdb)?;
+        Ok(Self { pipeline })
+    }
+
+    pub fn render<'rpass>(
+        &'rpass self,
+        transform_uniform: &'rpass Uniform<TransformUniform>,
+        render_pass: &mut wgpu::RenderPass<'rpass>,
+    ) {
+        self.pipeline.render(transform_uniform, render_pass);
+    }
+}
+
+fn settings_svg(rows: &[(&str, String)], selected: usize) -> String {
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 {WIDTH} {HEIGHT}" width="{WIDTH}" height="{HEIGHT}">"#
+    );
+    let _ = writeln!(
+        svg,
+        r#"<rect x="0" y="0" width="{WIDTH}" height="{HEIGHT}" fill="white"/>"#
+    );
+    text(&mut svg, 40.0, 60.0, 32.0, "start", "Settings");
+    text(
+        &mut svg,
+        40.0,
+        92.0,
+        16.0,
+        "start",
+        "Up and down pick a setting, left and right change it, Escape saves",
+    );
+
+    let top = 150.0;
+    for (row, (name, value)) in rows.iter().enumerate() {
+        let y = top + ROW_HEIGHT * row as f32;
+        if row == selected {
+            let _ = writeln!(
+                svg,
+                r#"<rect x="30" y="{}" width="{}" height="{ROW_HEIGHT}" fill="rgb(200,220,255)"/>"#,
+                y - ROW_HEIGHT + 10.0,
+                WIDTH - 60.0
+            );
+        }
+        text(&mut svg, 40.0, y, 20.0, "start", name);
+        text(&mut svg, 400.0, y, 20.0, "start", value);
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
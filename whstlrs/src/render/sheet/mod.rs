@@ -1,14 +1,24 @@
+use crate::config::Config;
+use crate::instrument::{HoleState, Instrument};
 use crate::scene::playing_scene::PlayingScene;
+use crate::song::score::NoteResult;
 use crate::song::SongEvent;
-use crate::song::SongNote;
+use crate::song::{Song, SongFile};
+use crate::whistle::WhistleKey;
 use crate::Context;
 use crate::TransformUniform;
 
+use std::fmt::Write;
+use std::path::Path;
+use std::time::Duration;
+
 use midly::MidiMessage;
+use resvg::usvg;
 use wgpu_jumpstart::wgpu;
 use wgpu_jumpstart::Gpu;
 
-mod pipeline;
+pub mod pipeline;
+use super::svg::{escape, FONT};
 use pipeline::SheetPipeline;
 use wgpu_jumpstart::Uniform;
 use winit::event::ElementState;
@@ -17,12 +27,76 @@ use winit::event::WindowEvent;
 
 pub struct SheetRenderer {
     sheet_pipeline: SheetPipeline,
+    instrument: Instrument,
+    whistle_key: WhistleKey,
+    /// The note with its shown fingering, an index in its alternates.
+    shown: Option<(u8, usize)>,
 }
 
 impl SheetRenderer {
-    pub fn new(gpu: &Gpu, transform_uniform: &Uniform<TransformUniform>) -> Self {
-        let sheet_pipeline = SheetPipeline::new(gpu, transform_uniform);
-        Self { sheet_pipeline }
+    /// Shows the sheet music of the song, or the `plain` page when it has none,
+    /// in the colours of the config.
+    pub fn new(
+        gpu: &Gpu,
+        transform_uniform: &Uniform<TransformUniform>,
+        song: &Song,
+        instrument: Instrument,
+        whistle_key: WhistleKey,
+        config: &Config,
+    ) -> Result<Self, String> {
+        let Some(svg) = &song.svg else {
+            return Self::plain(
+                gpu,
+                transform_uniform,
+                song,
+                instrument,
+                whistle_key,
+                config,
+            );
+        };
+        let fontdb = usvg::fontdb::Database::new();
+        let mut sheet_pipeline = SheetPipeline::from_svg(gpu, transform_uniform, svg, &fontdb)
+            .map_err(|e| match &song.svg_path {
+                Some(path) => format!("{}: {}", path.display(), e),
+                None => e,
+            })?;
+        sheet_pipeline.set_colors(config.note_color, config.hole_color);
+        Ok(Self {
+            sheet_pipeline,
+            instrument,
+            whistle_key,
+            shown: None,
+        })
+    }
+
+    /// A page with the title of the song and the finger holes, for songs
+    /// without sheet music.
+    pub fn plain(
+        gpu: &Gpu,
+        transform_uniform: &Uniform<TransformUniform>,
+        song: &Song,
+        instrument: Instrument,
+        whistle_key: WhistleKey,
+        config: &Config,
+    ) -> Result<Self, String> {
+        let mut fontdb = usvg::fontdb::Database::new();
+        fontdb.load_system_fonts();
+        let svg = plain_svg(&song.file, instrument.holes);
+        let mut sheet_pipeline =
+            SheetPipeline::from_svg(gpu, transform_uniform, svg.as_bytes(), &fontdb)?;
+        sheet_pipeline.set_colors(config.note_color, config.hole_color);
+        Ok(Self {
+            sheet_pipeline,
+            instrument,
+            whistle_key,
+            shown: None,
+        })
+    }
+
+    /// Shows the fingerings of the instrument in `whistle_key`.
+    pub fn set_instrument(&mut self, instrument: Instrument, whistle_key: WhistleKey) {
+        self.instrument = instrument;
+        self.whistle_key = whistle_key;
     }
 
     pub fn update_time(&mut self, gpu: &mut Gpu) {
@@ -37,92 +111,62 @@ impl SheetRenderer {
         };
 
         if is_on {
-            let note = SheetRenderer::midi2note(midi_key);
-            let holes = SheetRenderer::note2holes(&note);
-            //println!("{} {}", midi_key, note);
-            for i in (0..6).rev() {
-                let h: u16 = 1 << i;
-                let hole = format!("fingerhole-{}", (6 - i));
-                self.sheet_pipeline
-                    .fingerhole_states_mut()
-                    .entry(hole.into())
-                    .and_modify(|fingerhole| match holes & h == h {
-                        true => fingerhole.set_active(),
-                        false => fingerhole.set_inactive(),
-                    });
-            }
+            self.show_fingering(midi_key);
         } else {
             // mmmm
         }
     }
 
-    pub fn render<'rpass>(
-        &'rpass mut self,
-        transform_uniform: &'rpass Uniform<TransformUniform>,
-        render_pass: &mut wgpu::RenderPass<'rpass>,
-    ) {
-        self.sheet_pipeline.render(transform_uniform, render_pass);
+    /// Shows the finger holes to close for a note. A note the instrument
+    /// can't play opens all holes, and returns false.
+    pub fn show_fingering(&mut self, midi_key: u8) -> bool {
+        self.show_alternate(midi_key, 0)
     }
 
-    pub fn note2holes(note: &str) -> u16 {
-        match note {
-            "D" => 0b0_111111,
-            "E" => 0b0_111110,
-            "F" => 0b0_111101, // warble halfnote
-            "F#" => 0b0_111100,
-            "G" => 0b0_111000,
-            "G#" => 0b0_110111, // warble
-            "A" => 0b0_110000,
-            "A#" => 0b0_101111, // warble
-            "B" => 0b0_100000,
-            "C" => 0b0_011000,
-            "C#" => 0b0_000000,
-
-            "D'" => 0b1_011111,
-            "E'" => 0b1_111110,
-            "F'" => 0b1_111101, // warble
-            "F#'" => 0b1_111100,
-            "G'" => 0b1_111000,
-            "G#" => 0b1_110111, // warble
-            "A'" => 0b1_110000,
-            "A#'" => 0b1_101111, // warble
-            "B'" => 0b1_100000,
-            "C'" => 0b1_011100,
-            "C#'" => 0b1_000000,
-            "D''" => 0b1_011111,
-            _ => 0,
+    /// Shows the next alternate fingering of the shown note.
+    pub fn next_alternate(&mut self) {
+        let Some((midi_key, alternate)) = self.shown else {
+            return;
+        };
+        let count = self.instrument.fingerings(midi_key, self.whistle_key).len();
+        if count > 1 {
+            let alternate = (alternate + 1) % count;
+            log::info!("Fingering {} of {}", alternate + 1, count);
+            self.show_alternate(midi_key, alternate);
         }
     }
 
-    pub fn midi2note(midi_key: u8) -> String {
-        match midi_key {
-            62 => "D",
-            64 => "E",
-            65 => "F",
-            66 => "F#",
-            67 => "G",
-            68 => "G#",
-            69 => "A",
-            70 => "A#",
-            71 => "B",
-            72 => "C",
-            73 => "C#",
-            74 => "D'",
-
-            76 => "E'",
-            77 => "F'",
-            78 => "F#'",
-            79 => "G'",
-            80 => "G#'",
-            81 => "A'",
-            82 => "A#'",
-            83 => "B'",
-            84 => "C'",
-            85 => "C#'",
-            86 => "D''",
-            _ => "?",
+    fn show_alternate(&mut self, midi_key: u8, alternate: usize) -> bool {
+        let fingering = self
+            .instrument
+            .fingerings(midi_key, self.whistle_key)
+            .get(alternate);
+        if fingering.is_none() {
+            log::warn!(
+                "Note {} is out of range of the {} in {}",
+                midi_key,
+                self.instrument.name,
+                self.whistle_key.name()
+            );
         }
-        .into()
+        for i in 0..self.instrument.holes {
+            let state = fingering.map_or(HoleState::Open, |f| f.holes[i]);
+            let hole = format!("fingerhole-{}", i + 1);
+            self.sheet_pipeline
+                .fingerhole_states_mut()
+                .entry(hole)
+                .and_modify(|fingerhole| fingerhole.set_state(state));
+        }
+        self.shown = fingering.map(|_| (midi_key, alternate));
+        fingering.is_some()
+    }
+
+    pub fn render<'rpass>(
+        &'rpass mut self,
+        transform_uniform: &'rpass Uniform<TransformUniform>,
+        render_pass: &mut wgpu::RenderPass<'rpass>,
+    ) {
+        self.sheet_pipeline.render(transform_uniform, render_pass);
     }
 
     pub fn song_events(&mut self, events: &[&SongEvent]) {
@@ -142,6 +186,25 @@ impl SheetRenderer {
         }
     }
 
+    /// Clears the highlighted noteheads, after jumping to another position.
+    pub fn reset_noteheads(&mut self) {
+        self.sheet_pipeline
+            .notehead_states_mut()
+            .values_mut()
+            .for_each(|note| note.set_inactive());
+    }
+
+    /// Colours a notehead by how the player played it.
+    pub fn note_result(&mut self, notehead_id: &str, result: Option<NoteResult>) {
+        if let Some(note) = self
+            .sheet_pipeline
+            .notehead_states_mut()
+            .get_mut(notehead_id)
+        {
+            note.set_result(result);
+        }
+    }
+
     pub fn handle_window_event(
         scene: &mut PlayingScene,
         ctx: &mut Context,
@@ -170,31 +233,27 @@ impl SheetRenderer {
             let y = pos.y * (720.0 / h);
 
             if let Some(notehead_id) = scene.sheet.sheet_pipeline.find_notehead(x, y) {
-                let note = ctx
-                    .song
-                    .as_ref()
-                    .unwrap()
+                let note = scene
+                    .player
+                    .playback()
+                    .song()
                     .file
                     .notes
                     .iter()
-                    .find(|note| note.notehead_id == notehead_id);
-                if let Some(&SongNote { midi_key, .. }) = note {
-                    let note = SheetRenderer::midi2note(midi_key);
-                    let holes = SheetRenderer::note2holes(&note);
-                    //println!("{} {}", midi_key, note);
-                    for i in (0..6).rev() {
-                        let h: u16 = 1 << i;
-                        let hole = format!("fingerhole-{}", (6 - i));
-                        scene
-                            .sheet
-                            .sheet_pipeline
-                            .fingerhole_states_mut()
-                            .entry(hole.into())
-                            .and_modify(|fingerhole| match holes & h == h {
-                                true => fingerhole.set_active(),
-                                false => fingerhole.set_inactive(),
-                            });
-                    }
+                    .find(|note| note.notehead_id == notehead_id)
+                    .map(|note| (note.timestamp, note.duration_length, note.midi_key));
+                if let Some((timestamp, duration_length, _)) =
+                    note.filter(|_| ctx.window_state.modifiers_state.shift_key())
+                {
+                    // shift-click picks the loop region
+                    scene.loop_point(
+                        Duration::from_secs_f32(timestamp),
+                        Duration::from_secs_f32(timestamp + duration_length),
+                    );
+                    return true;
+                }
+                if let Some((_, _, midi_key)) = note {
+                    scene.sheet.show_fingering(midi_key);
                     return true;
                 } else {
                     return false;
@@ -206,3 +265,85 @@ impl SheetRenderer {
         return false;
     }
 }
+
+/// Draws the sheet music of the song, or the `plain` page when it has none,
+/// to a PNG file `scale` times the size of the sheet. No window is needed.
+pub fn render_png(song: &Song, holes: usize, path: &Path, scale: f32) -> Result<(), String> {
+    let mut fontdb = usvg::fontdb::Database::new();
+    fontdb.load_system_fonts();
+    let plain;
+    let svg: &[u8] = match &song.svg {
+        Some(svg) => svg,
+        None => {
+            plain = plain_svg(&song.file, holes);
+            plain.as_bytes()
+        }
+    };
+    let tree = usvg::Tree::from_data(svg, &usvg::Options::default(), &fontdb)
+        .map_err(|e| format!("Could not read the sheet music: {}", e))?;
+    let size = tree
+        .size()
+        .to_int_size()
+        .scale_by(scale)
+        .ok_or("Invalid scale")?;
+    let mut pixmap = resvg::tiny_skia::Pixmap::new(size.width(), size.height())
+        .ok_or("Invalid size of the sheet music")?;
+    pixmap.fill(resvg::tiny_skia::Color::WHITE);
+    resvg::render(
+        &tree,
+        resvg::tiny_skia::Transform::from_scale(scale, scale),
+        &mut pixmap.as_mut(),
+    );
+    pixmap
+        .save_png(path)
+        .map_err(|e| format!("Could not write {}: {}", path.display(), e))
+}
+
+fn plain_svg(song: &SongFile, holes: usize) -> String {
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 1280 720" width="1280" height="720">"#
+    );
+    let title = song.info.title.as_deref().unwrap_or(&song.name);
+    let _ = writeln!(
+        svg,
+        r#"<text x="640" y="80" font-family="{FONT}" font-size="36" text-anchor="middle">{}</text>"#,
+        escape(title)
+    );
+    let details: Vec<String> = [
+        song.info.rhythm.clone(),
+        song.info.key.as_ref().map(|key| format!("Key {}", key)),
+        song.info
+            .meter
+            .map(|m| format!("{}/{}", m.numerator, m.denominator)),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    let _ = writeln!(
+        svg,
+        r#"<text x="640" y="120" font-family="{FONT}" font-size="20" text-anchor="middle">{}</text>"#,
+        escape(&details.join(", "))
+    );
+
+    // the body of the instrument, with the holes from the top down
+    let spacing = 60.0;
+    let top = 400.0 - spacing * holes as f32 / 2.0;
+    let _ = writeln!(
+        svg,
+        r#"<rect x="610" y="{}" width="60" height="{}" fill="none" stroke="black" stroke-width="3"/>"#,
+        top - spacing,
+        spacing * (holes as f32 + 1.0)
+    );
+    for i in 0..holes {
+        let _ = writeln!(
+            svg,
+            r#"<g id="fingerhole-{}"><circle cx="640" cy="{}" r="20" fill="white" stroke="black" stroke-width="3"/></g>"#,
+            i + 1,
+            top + spacing * (i as f32 + 0.5)
+        );
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
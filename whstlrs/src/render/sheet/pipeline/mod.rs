@@ -7,7 +7,10 @@ use usvg::Color;
 use wgpu_jumpstart::wgpu::util::DeviceExt;
 use wgpu_jumpstart::{wgpu, Gpu, RenderPipelineBuilder, TransformUniform, Uniform};
 
-use lyon::math::Point;
+use crate::instrument::HoleState;
+use crate::song::score::NoteResult;
+
+use lyon::math::{point, Point};
 use lyon::path::PathEvent;
 use lyon::tessellation::geometry_builder::*;
 use lyon::tessellation::{self, FillOptions, FillTessellator, StrokeOptions, StrokeTessellator};
@@ -16,22 +19,46 @@ pub const FALLBACK_COLOR: usvg::Color = usvg::Color {
     green: 0,
     blue: 0,
 };
+/// Line segments of the half disc drawn in a half covered finger hole.
+const HALF_DISC_SEGMENTS: usize = 24;
+/// Colour of a lit beat marker, a group with an id starting with `beat-`.
+const BEAT_COLOR: Color = Color {
+    red: 255,
+    green: 128,
+    blue: 0,
+};
+
+/// Colour of a note with its score, black before it is scored.
+pub fn result_color(result: Option<NoteResult>) -> Color {
+    match result {
+        Some(NoteResult::Hit) => Color::new_rgb(0, 160, 0),
+        Some(NoteResult::Early) => Color::new_rgb(230, 180, 0),
+        Some(NoteResult::Late) => Color::new_rgb(230, 110, 0),
+        Some(NoteResult::Wrong) => Color::new_rgb(220, 0, 0),
+        Some(NoteResult::Missed) => Color::new_rgb(150, 150, 150),
+        None => Color::new_rgb(0, 0, 0),
+    }
+}
 
 #[derive(Debug)]
 pub struct NoteHeadState {
     active: bool,
+    result: Option<NoteResult>,
 }
 
 impl NoteHeadState {
     pub fn new(active: bool) -> Self {
-        Self { active }
+        Self {
+            active,
+            result: None,
+        }
     }
-    pub fn color(&self) -> Color {
+    /// `active` is the colour of the note while it is played.
+    pub fn color(&self, active: Color) -> Color {
         if self.active {
-            Color::new_rgb(0, 0, 255)
-        } else {
-            Color::new_rgb(0, 0, 0)
+            return active;
         }
+        result_color(self.result)
     }
     pub fn set_active(&mut self) {
         self.active = true;
@@ -39,29 +66,36 @@ impl NoteHeadState {
     pub fn set_inactive(&mut self) {
         self.active = false;
     }
+    pub fn set_result(&mut self, result: Option<NoteResult>) {
+        self.result = result;
+    }
 }
 
 #[derive(Debug)]
 pub struct FingerHolestate {
-    active: bool,
+    state: HoleState,
 }
 
 impl FingerHolestate {
-    pub fn new(active: bool) -> Self {
-        Self { active }
+    pub fn new(state: HoleState) -> Self {
+        Self { state }
     }
-    pub fn color(&self) -> Color {
-        if self.active {
-            Color::new_rgb(255, 0, 0)
-        } else {
-            Color::new_rgb(255, 255, 255)
+    /// `closed` is the colour of a hole to close.
+    pub fn color(&self, closed: Color) -> Color {
+        match self.state {
+            HoleState::Closed => closed,
+            HoleState::Open | HoleState::Half => Color::new_rgb(255, 255, 255),
         }
     }
-    pub fn set_active(&mut self) {
-        self.active = true;
+    /// Colour of the lower half of the hole.
+    pub fn half_color(&self, closed: Color) -> Color {
+        match self.state {
+            HoleState::Closed | HoleState::Half => closed,
+            HoleState::Open => Color::new_rgb(255, 255, 255),
+        }
     }
-    pub fn set_inactive(&mut self) {
-        self.active = false;
+    pub fn set_state(&mut self, state: HoleState) {
+        self.state = state;
     }
 }
 
@@ -70,10 +104,16 @@ pub struct SheetPipeline {
     mesh: Mesh,
     uniform: MyUniform,
     groups: HashMap<String, Vec<usize>>,
+    /// Primitives of the lower halves of the finger holes.
+    half_groups: HashMap<String, Vec<usize>>,
     pub notehead_states: HashMap<String, NoteHeadState>,
     pub fingerhole_states: HashMap<String, FingerHolestate>,
+    /// Beat markers that are lit, the others keep the colour of the SVG.
+    pub beat_states: HashMap<String, bool>,
     bboxes: Vec<(Rect, String)>,
     primitives: Vec<GpuPrimitive>,
+    note_color: Color,
+    hole_color: Color,
 }
 
 pub struct Mesh {
@@ -230,24 +270,28 @@ fn collect_paths(
             collect_paths(group, paths, bboxes, id_attr);
         } else if let usvg::Node::Path(ref p) = node {
             paths.push((*p.to_owned(), id_attr.to_string()));
+        } else if let usvg::Node::Text(ref text) = node {
+            collect_paths(text.flattened(), paths, bboxes, id_attr);
         }
     }
 }
 
 impl<'a> SheetPipeline {
-    pub fn new(gpu: &Gpu, transform_uniform: &Uniform<TransformUniform>) -> Self {
-        // SVG
-
+    /// Builds the pipeline for an SVG document, text is drawn with the fonts in `fontdb`.
+    pub fn from_svg(
+        gpu: &Gpu,
+        transform_uniform: &Uniform<TransformUniform>,
+        file_data: &[u8],
+        fontdb: &usvg::fontdb::Database,
+    ) -> Result<Self, String> {
         let mut fill_tess = FillTessellator::new();
         let mut stroke_tess = StrokeTessellator::new();
         let mut mesh: VertexBuffers<_, u32> = VertexBuffers::new();
         let mut groups: HashMap<String, Vec<usize>> = HashMap::new();
-        let fontdb = usvg::fontdb::Database::new();
+        let mut half_groups: HashMap<String, Vec<usize>> = HashMap::new();
         let opt = usvg::Options::default();
-        //let file_data = std::fs::read(filename).unwrap();
-        let file_data =
-            include_bytes!("../../../../../contrib/starofthecountydown/starofthecountydown2.svg");
-        let rtree = usvg::Tree::from_data(file_data, &opt, &fontdb).unwrap();
+        let rtree = usvg::Tree::from_data(file_data, &opt, fontdb)
+            .map_err(|e| format!("Could not load SVG: {}", e))?;
         let mut transforms = Vec::new();
         let mut primitives = Vec::new();
 
@@ -292,7 +336,7 @@ impl<'a> SheetPipeline {
 
                 if !id_attr.is_empty() {
                     let prim_id = primitives.len() - 1;
-                    (*groups.entry(id_attr).or_default()).push(prim_id);
+                    (*groups.entry(id_attr.clone()).or_default()).push(prim_id);
                 }
 
                 fill_tess
@@ -306,7 +350,24 @@ impl<'a> SheetPipeline {
                             },
                         ),
                     )
-                    .expect("Error during tessellation!");
+                    .map_err(|e| format!("Could not tessellate the SVG: {:?}", e))?;
+
+                if id_attr.starts_with("fingerhole-") {
+                    // drawn over the hole, in the colour of a covered hole
+                    // when it is half covered
+                    primitives.push(GpuPrimitive::new(transform_idx, color, 0.0));
+                    (*half_groups.entry(id_attr.clone()).or_default()).push(primitives.len() - 1);
+                    let _ = fill_tess.tessellate_path(
+                        &half_disc(p.data().bounds()),
+                        &FillOptions::tolerance(0.01),
+                        &mut BuffersBuilder::new(
+                            &mut mesh,
+                            VertexCtor {
+                                prim_id: primitives.len() as u32 - 1,
+                            },
+                        ),
+                    );
+                }
             }
 
             if let Some(ref stroke) = p.stroke() {
@@ -423,18 +484,33 @@ impl<'a> SheetPipeline {
         let fingerhole_states = groups
             .keys()
             .filter(|k| k.starts_with("fingerhole-"))
-            .map(|k| (k.to_string(), FingerHolestate { active: false }))
+            .map(|k| (k.to_string(), FingerHolestate::new(HoleState::Open)))
             .collect::<HashMap<String, FingerHolestate>>();
-        Self {
+        let beat_states = groups
+            .keys()
+            .filter(|k| k.starts_with("beat-"))
+            .map(|k| (k.to_string(), false))
+            .collect::<HashMap<String, bool>>();
+        Ok(Self {
             render_pipeline,
             mesh,
             uniform: myuniform,
             groups,
+            half_groups,
             notehead_states,
             fingerhole_states,
+            beat_states,
             bboxes,
             primitives,
-        }
+            note_color: Color::new_rgb(0, 0, 255),
+            hole_color: Color::new_rgb(255, 0, 0),
+        })
+    }
+
+    /// Colours of the played notes and of the finger holes to close.
+    pub fn set_colors(&mut self, note_color: Color, hole_color: Color) {
+        self.note_color = note_color;
+        self.hole_color = hole_color;
     }
 
     pub fn update_time(&mut self, gpu: &mut Gpu) {
@@ -442,7 +518,7 @@ impl<'a> SheetPipeline {
 
         for (id_attr, notehead) in self.notehead_states.iter() {
             if let Some(prim_ids) = self.groups.get(id_attr) {
-                let color: usvg::Color = notehead.color();
+                let color: usvg::Color = notehead.color(self.note_color);
                 for &prim_id in prim_ids {
                     prims[prim_id] = GpuPrimitive::new(prims[prim_id].transform, color, 0.0);
                 }
@@ -451,13 +527,27 @@ impl<'a> SheetPipeline {
 
         for (id_attr, fingerhole) in self.fingerhole_states.iter() {
             if let Some(prim_ids) = self.groups.get(id_attr) {
-                let color: usvg::Color = fingerhole.color();
+                let color: usvg::Color = fingerhole.color(self.hole_color);
+                for &prim_id in prim_ids {
+                    prims[prim_id] = GpuPrimitive::new(prims[prim_id].transform, color, 0.0);
+                }
+            }
+            if let Some(prim_ids) = self.half_groups.get(id_attr) {
+                let color: usvg::Color = fingerhole.half_color(self.hole_color);
                 for &prim_id in prim_ids {
                     prims[prim_id] = GpuPrimitive::new(prims[prim_id].transform, color, 0.0);
                 }
             }
         }
 
+        for (id_attr, _) in self.beat_states.iter().filter(|(_, lit)| **lit) {
+            if let Some(prim_ids) = self.groups.get(id_attr) {
+                for &prim_id in prim_ids {
+                    prims[prim_id] = GpuPrimitive::new(prims[prim_id].transform, BEAT_COLOR, 0.0);
+                }
+            }
+        }
+
         let _ = &gpu
             .queue
             .write_buffer(&self.uniform.prims_ssbo, 0, bytemuck::cast_slice(&prims));
@@ -481,6 +571,10 @@ impl<'a> SheetPipeline {
         &mut self.fingerhole_states
     }
 
+    pub fn beat_states_mut(&mut self) -> &mut HashMap<String, bool> {
+        &mut self.beat_states
+    }
+
     pub fn render(
         &'a self,
         transform_uniform: &'a Uniform<TransformUniform>,
@@ -662,6 +756,20 @@ impl<'l> Iterator for PathConvIter<'l> {
     }
 }
 
+/// The lower half of the ellipse in `bounds`.
+fn half_disc(bounds: Rect) -> lyon::path::Path {
+    let (rx, ry) = (bounds.width() / 2.0, bounds.height() / 2.0);
+    let (cx, cy) = (bounds.x() + rx, bounds.y() + ry);
+    let mut builder = lyon::path::Path::builder();
+    builder.begin(point(cx + rx, cy));
+    for i in 1..=HALF_DISC_SEGMENTS {
+        let angle = std::f32::consts::PI * i as f32 / HALF_DISC_SEGMENTS as f32;
+        builder.line_to(point(cx + rx * angle.cos(), cy + ry * angle.sin()));
+    }
+    builder.close();
+    builder.build()
+}
+
 pub fn convert_path(p: &usvg::Path) -> PathConvIter {
     PathConvIter {
         iter: p.data().segments(),
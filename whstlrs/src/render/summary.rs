@@ -0,0 +1,169 @@
+use std::fmt::Write;
+
+use resvg::usvg;
+use wgpu_jumpstart::{wgpu, Gpu, TransformUniform, Uniform};
+
+use super::sheet::pipeline::{result_color, SheetPipeline};
+use super::svg::text;
+use crate::song::score::{NoteResult, ScoreSummary, TimingWindows};
+
+const WIDTH: f32 = 1280.0;
+const HEIGHT: f32 = 720.0;
+
+/// The score of a run-through at the end of the song: the accuracy, the
+/// number of notes per result and how far off the timing was.
+pub struct SummaryRenderer {
+    pipeline: SheetPipeline,
+}
+
+impl SummaryRenderer {
+    pub fn new(
+        gpu: &Gpu,
+        transform_uniform: &Uniform<TransformUniform>,
+        summary: &ScoreSummary,
+        windows: TimingWindows,
+        song: &str,
+    ) -> Result<Self, String> {
+        let mut fontdb = usvg::fontdb::Database::new();
+        fontdb.load_system_fonts();
+        let svg = summary_svg(summary, windows, song);
+        let pipeline = SheetPipeline::from_svg(gpu, transform_uniform, svg.as_bytes(), &fontdb)?;
+        Ok(Self { pipeline })
+    }
+
+    pub fn render<'rpass>(
+        &'rpass self,
+        transform_uniform: &'rpass Uniform<TransformUniform>,
+        render_pass: &mut wgpu::RenderPass<'rpass>,
+    ) {
+        self.pipeline.render(transform_uniform, render_pass);
+    }
+}
+
+fn summary_svg(summary: &ScoreSummary, windows: TimingWindows, song: &str) -> String {
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 {WIDTH} {HEIGHT}" width="{WIDTH}" height="{HEIGHT}">"#
+    );
+    let _ = writeln!(
+        svg,
+        r#"<rect x="0" y="0" width="{WIDTH}" height="{HEIGHT}" fill="white"/>"#
+    );
+    text(
+        &mut svg,
+        40.0,
+        60.0,
+        32.0,
+        "start",
+        &format!("Score: {}", song),
+    );
+    text(
+        &mut svg,
+        40.0,
+        95.0,
+        18.0,
+        "start",
+        &format!(
+            "A hit is within {} ms of the note, early or late within {} ms",
+            windows.hit.as_millis(),
+            windows.ok.as_millis()
+        ),
+    );
+
+    text(
+        &mut svg,
+        240.0,
+        330.0,
+        120.0,
+        "middle",
+        &format!("{:.0}%", summary.accuracy * 100.0),
+    );
+    text(&mut svg, 240.0, 380.0, 24.0, "middle", "accuracy");
+
+    // notes per result
+    let rows = [
+        ("Hit", summary.hit, result_color(Some(NoteResult::Hit))),
+        (
+            "Early",
+            summary.early,
+            result_color(Some(NoteResult::Early)),
+        ),
+        ("Late", summary.late, result_color(Some(NoteResult::Late))),
+        (
+            "Wrong",
+            summary.wrong,
+            result_color(Some(NoteResult::Wrong)),
+        ),
+        (
+            "Missed",
+            summary.missed,
+            result_color(Some(NoteResult::Missed)),
+        ),
+        ("Extra", summary.extra, result_color(None)),
+    ];
+    let max = rows
+        .iter()
+        .map(|&(_, count, _)| count)
+        .max()
+        .unwrap_or(0)
+        .max(1) as f32;
+    let (left, top, width) = (520.0, 170.0, 500.0);
+    for (i, (name, count, color)) in rows.iter().enumerate() {
+        let y = top + 44.0 * i as f32;
+        text(&mut svg, left, y + 22.0, 18.0, "start", name);
+        let bar_width = (*count as f32 / max * width).max(1.0);
+        let _ = writeln!(
+            svg,
+            r#"<rect x="{}" y="{y}" width="{bar_width}" height="30" fill="rgb({},{},{})"/>"#,
+            left + 90.0,
+            color.red,
+            color.green,
+            color.blue
+        );
+        text(
+            &mut svg,
+            left + 100.0 + bar_width,
+            y + 22.0,
+            18.0,
+            "start",
+            &count.to_string(),
+        );
+    }
+
+    // timing deviation
+    let played = summary.hit + summary.early + summary.late;
+    let timing = if played == 0 {
+        vec!["No notes were played in time".to_string()]
+    } else {
+        vec![
+            format!(
+                "Timing {:+.0} ms on average, {}",
+                summary.mean_deviation * 1000.0,
+                if summary.mean_deviation < 0.0 {
+                    "ahead of the beat"
+                } else {
+                    "behind the beat"
+                }
+            ),
+            format!(
+                "Off by {:.0} ms on average, with a standard deviation of {:.0} ms",
+                summary.mean_abs_deviation * 1000.0,
+                summary.deviation_std_dev * 1000.0
+            ),
+        ]
+    };
+    for (i, line) in timing.iter().enumerate() {
+        text(&mut svg, 40.0, 500.0 + 32.0 * i as f32, 20.0, "start", line);
+    }
+    text(
+        &mut svg,
+        40.0,
+        HEIGHT - 40.0,
+        16.0,
+        "start",
+        "Home plays the song again, Escape goes back to the songs",
+    );
+    svg.push_str("</svg>\n");
+    svg
+}
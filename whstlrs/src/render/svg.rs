@@ -0,0 +1,23 @@
+//! Helpers for the SVG documents the screens are drawn from.
+
+use std::fmt::Write;
+
+/// The font of the text, the first one of them that is installed.
+pub(super) const FONT: &str = "DejaVu Sans, Arial, Helvetica, sans-serif";
+
+/// Escapes `text` for the content or an attribute value of an element.
+pub(super) fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Adds a line of text, `anchor` is `start`, `middle` or `end`.
+pub(super) fn text(svg: &mut String, x: f32, y: f32, size: f32, anchor: &str, content: &str) {
+    let _ = writeln!(
+        svg,
+        r#"<text x="{x}" y="{y}" font-family="{FONT}" font-size="{size}" text-anchor="{anchor}">{}</text>"#,
+        escape(content)
+    );
+}
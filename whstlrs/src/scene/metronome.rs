@@ -0,0 +1,175 @@
+use std::time::Duration;
+
+use midly::{
+    num::{u4, u7},
+    MidiMessage,
+};
+
+use crate::{
+    output_manager::{OutputConnection, CLICK, CLICK_ACCENT, DRUM_CHANNEL},
+    song::SongFile,
+};
+
+const CLICK_VELOCITY: u8 = 100;
+/// How long the beat pulse stays lit.
+const PULSE: Duration = Duration::from_millis(100);
+
+struct CountIn {
+    elapsed: Duration,
+    /// Length of a beat in real time.
+    beat: Duration,
+    beats: u32,
+    clicked: u32,
+}
+
+/// Clicks the beats of the song on the percussion channel, and counts in
+/// before the playback starts.
+pub struct Metronome {
+    output: OutputConnection,
+    /// Beats in song time, true for the first beat of a bar.
+    beats: Vec<(Duration, bool)>,
+    beats_per_bar: u32,
+    enabled: bool,
+    next: usize,
+    count_in_bars: u32,
+    count_in: Option<CountIn>,
+    /// Real time since the last beat.
+    since_beat: Duration,
+}
+
+impl Metronome {
+    pub fn new(song: &SongFile, output: OutputConnection) -> Self {
+        Self {
+            output,
+            beats: song.beats(),
+            beats_per_bar: song.info.meter.map_or(4, |m| m.numerator.max(1)) as u32,
+            enabled: false,
+            next: 0,
+            count_in_bars: 1,
+            count_in: None,
+            since_beat: PULSE,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Turns the clicks during the playback on or off, the count-in always
+    /// clicks. A song without beats, that has no tempo, stays silent.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled && !self.beats.is_empty();
+    }
+
+    pub fn count_in_bars(&self) -> u32 {
+        self.count_in_bars
+    }
+
+    pub fn set_count_in_bars(&mut self, bars: u32) {
+        self.count_in_bars = bars;
+    }
+
+    /// Starts counting in before the playback continues at `time`, with the
+    /// playback at `speed`.
+    pub fn start_count_in(&mut self, time: Duration, speed: f32) {
+        self.seek(time);
+        let beats = self.count_in_bars * self.beats_per_bar;
+        if beats == 0 || self.beats.is_empty() {
+            self.count_in = None;
+            return;
+        }
+        self.count_in = Some(CountIn {
+            elapsed: Duration::ZERO,
+            beat: self.beat_length(time).div_f32(speed.max(f32::EPSILON)),
+            beats,
+            clicked: 0,
+        });
+    }
+
+    pub fn is_counting_in(&self) -> bool {
+        self.count_in.is_some()
+    }
+
+    pub fn stop_count_in(&mut self) {
+        self.count_in = None;
+    }
+
+    /// Length of the beat at `time`, in song time.
+    fn beat_length(&self, time: Duration) -> Duration {
+        let index = self.beats.partition_point(|(start, _)| *start <= time);
+        let beat = |i: usize| match (self.beats.get(i), self.beats.get(i + 1)) {
+            (Some((a, _)), Some((b, _))) => Some(*b - *a),
+            _ => None,
+        };
+        beat(index.saturating_sub(1))
+            .or_else(|| beat(self.beats.len().saturating_sub(2)))
+            .unwrap_or(Duration::from_millis(500))
+    }
+
+    /// Goes on from `time` after a jump, without clicking the beats in between.
+    pub fn seek(&mut self, time: Duration) {
+        self.next = self.beats.partition_point(|(start, _)| *start < time);
+    }
+
+    /// Clicks the count-in, `delta` is real time.
+    pub fn count_in(&mut self, delta: Duration) {
+        self.since_beat += delta;
+        let Some(count_in) = self.count_in.as_mut() else {
+            return;
+        };
+        count_in.elapsed += delta;
+        let mut clicks = Vec::new();
+        while count_in.clicked < count_in.beats
+            && count_in.elapsed >= count_in.beat * count_in.clicked
+        {
+            clicks.push(count_in.clicked % self.beats_per_bar == 0);
+            count_in.clicked += 1;
+        }
+        if count_in.elapsed >= count_in.beat * count_in.beats {
+            self.count_in = None;
+        }
+        for downbeat in clicks {
+            self.click(downbeat);
+        }
+    }
+
+    /// Clicks the beats up to the song position `time`.
+    pub fn update(&mut self, delta: Duration, time: Duration) {
+        self.since_beat += delta;
+        while let Some(&(start, downbeat)) = self.beats.get(self.next) {
+            if start > time {
+                break;
+            }
+            self.next += 1;
+            self.since_beat = Duration::ZERO;
+            if self.enabled {
+                self.click(downbeat);
+            }
+        }
+    }
+
+    /// The beat pulse is lit, shortly after each beat.
+    pub fn pulse(&self) -> bool {
+        self.since_beat < PULSE
+    }
+
+    fn click(&mut self, downbeat: bool) {
+        self.since_beat = Duration::ZERO;
+        let key = u7::new(if downbeat { CLICK_ACCENT } else { CLICK });
+        let channel = u4::new(DRUM_CHANNEL);
+        self.output.midi_event(
+            channel,
+            MidiMessage::NoteOn {
+                key,
+                vel: u7::new(CLICK_VELOCITY),
+            },
+        );
+        self.output.midi_event(
+            channel,
+            MidiMessage::NoteOff {
+                key,
+                vel: u7::new(0),
+            },
+        );
+    }
+}
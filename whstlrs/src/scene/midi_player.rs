@@ -1,6 +1,10 @@
+use std::collections::HashSet;
 use std::time::Duration;
 
-use midly::num::u4;
+use midly::{
+    num::{u4, u7},
+    MidiMessage,
+};
 
 use crate::{
     output_manager::OutputConnection,
@@ -10,22 +14,92 @@ use crate::{
 pub struct MidiPlayer {
     output: OutputConnection,
     playback: PlaybackState,
+    /// Notes sounding on the output, by channel and key.
+    held_notes: HashSet<(u8, u8)>,
 }
 impl MidiPlayer {
-    pub fn new(song: Song) -> Self {
-        let output = OutputConnection::new();
+    pub fn new(song: Song, output: OutputConnection) -> Self {
         MidiPlayer {
             output,
             playback: PlaybackState::new(song.into()),
+            held_notes: HashSet::new(),
         }
     }
 
+    pub fn playback(&self) -> &PlaybackState {
+        &self.playback
+    }
+
+    pub fn playback_mut(&mut self) -> &mut PlaybackState {
+        &mut self.playback
+    }
+
     pub fn update(&mut self, delta: Duration) -> Vec<&SongEvent> {
         let events = self.playback.update(delta);
         events.iter().for_each(|event| {
+            match event.message {
+                MidiMessage::NoteOn { key, vel } if vel > 0 => {
+                    self.held_notes.insert((event.channel, key.as_int()));
+                }
+                MidiMessage::NoteOn { key, .. } | MidiMessage::NoteOff { key, .. } => {
+                    self.held_notes.remove(&(event.channel, key.as_int()));
+                }
+                _ => {}
+            }
             self.output
                 .midi_event(u4::new(event.channel), event.message);
         });
         events
     }
+
+    pub fn is_paused(&self) -> bool {
+        self.playback.is_paused()
+    }
+
+    pub fn pause(&mut self) {
+        self.playback.pause();
+        self.release_notes();
+    }
+
+    pub fn resume(&mut self) {
+        self.playback.resume();
+    }
+
+    pub fn seek(&mut self, time: Duration) {
+        self.release_notes();
+        self.playback.seek(time);
+    }
+
+    pub fn seek_to_bar(&mut self, bar: usize) {
+        self.release_notes();
+        self.playback.seek_to_bar(bar);
+    }
+
+    pub fn restart(&mut self) {
+        self.release_notes();
+        self.playback.restart();
+    }
+
+    pub fn next_repetition(&mut self, successful: bool) {
+        self.release_notes();
+        self.playback.next_repetition(successful);
+    }
+
+    pub fn transpose(&mut self, semitones: i8, octaves: i8) -> Result<(), String> {
+        self.release_notes();
+        self.playback.transpose(semitones, octaves)
+    }
+
+    /// Sends a NoteOff for every note that is still sounding.
+    fn release_notes(&mut self) {
+        for (channel, key) in self.held_notes.drain() {
+            self.output.midi_event(
+                u4::new(channel),
+                MidiMessage::NoteOff {
+                    key: u7::new(key),
+                    vel: u7::new(0),
+                },
+            );
+        }
+    }
 }
@@ -0,0 +1,66 @@
+use std::path::Path;
+use std::time::Duration;
+
+use midly::MidiMessage;
+
+use crate::song::{write_midi, SongInfo};
+
+/// Records what the player sends, to be written as a Standard MIDI File.
+pub struct MidiRecorder {
+    recording: bool,
+    /// Events at their time in the song, so the take lines up with it.
+    events: Vec<(Duration, u8, MidiMessage)>,
+}
+
+impl MidiRecorder {
+    pub fn new() -> Self {
+        Self {
+            recording: false,
+            events: Vec::new(),
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording
+    }
+
+    pub fn start(&mut self) {
+        self.events.clear();
+        self.recording = true;
+    }
+
+    pub fn stop(&mut self) {
+        self.recording = false;
+    }
+
+    /// Records over the take from `time` on, after the song jumped back there.
+    pub fn rewind(&mut self, time: Duration) {
+        self.events.retain(|(timestamp, ..)| *timestamp < time);
+    }
+
+    /// Records a message played at `time` in the song.
+    pub fn midi_event(&mut self, time: Duration, channel: u8, message: &MidiMessage) {
+        if !self.recording {
+            return;
+        }
+        match message {
+            MidiMessage::NoteOn { .. }
+            | MidiMessage::NoteOff { .. }
+            | MidiMessage::Controller { .. }
+            | MidiMessage::PitchBend { .. } => {
+                self.events.push((time, channel, *message));
+            }
+            _ => (),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// Writes the take as a MIDI file at the tempo and meter of the song, so
+    /// it lines up with the bars of the song.
+    pub fn save<P: AsRef<Path>>(&self, path: P, name: &str, info: &SongInfo) -> Result<(), String> {
+        write_midi(path, name, info.pace(), info.meter, &self.events)
+    }
+}
@@ -1,7 +1,13 @@
+pub mod metronome;
 pub mod midi_player;
+pub mod midi_recorder;
 pub mod playing_scene;
+pub mod settings;
+pub mod song_picker;
+pub mod stack;
 
 use crate::context::Context;
+use crate::song::Song;
 use midly::MidiMessage;
 use std::time::Duration;
 use wgpu_jumpstart::{wgpu, TransformUniform, Uniform};
@@ -16,4 +22,39 @@ pub trait Scene {
     );
     fn window_event(&mut self, ctx: &mut Context, event: &WindowEvent) {}
     fn midi_event(&mut self, ctx: &mut Context, channel: u8, message: &MidiMessage) {}
+    /// A MIDI input device was connected or disconnected.
+    fn device_event(&mut self, _ctx: &mut Context, _name: &str, _connected: bool) {}
+    /// Typed keys go to the scene, instead of the app and the keyboard whistle.
+    fn wants_text_input(&self) -> bool {
+        false
+    }
+    /// The scenes below show through, this one is drawn on top of them.
+    fn is_overlay(&self) -> bool {
+        false
+    }
+    /// The scene is on top of the stack, it gets the events from now on.
+    fn enter(&mut self, _ctx: &mut Context) {}
+    /// The scene is covered by another one, or removed.
+    fn leave(&mut self, _ctx: &mut Context) {}
+}
+
+/// A scene to show, made when the request reaches the scene stack.
+#[derive(Debug)]
+pub enum SceneRequest {
+    SongPicker,
+    Settings,
+    Playing(Box<Song>),
+}
+
+impl SceneRequest {
+    pub fn build(self, ctx: &mut Context) -> Result<Box<dyn Scene>, String> {
+        Ok(match self {
+            SceneRequest::SongPicker => Box::new(song_picker::SongPicker::new(ctx)),
+            SceneRequest::Settings => Box::new(settings::SettingsScene::new(ctx)),
+            SceneRequest::Playing(song) => {
+                ctx.song = Some(*song.clone());
+                Box::new(playing_scene::PlayingScene::new(ctx, *song)?)
+            }
+        })
+    }
 }
@@ -1,6 +1,20 @@
 use std::time::Duration;
 
-use crate::{context::Context, scene::midi_player::MidiPlayer, song::Song};
+use crate::{
+    config::MAX_COUNT_IN_BARS,
+    context::Context,
+    export::{self, ExportOptions},
+    history::{History, Session},
+    instrument::Instrument,
+    output_manager::{PROGRAM_FLUTE, PROGRAM_WHISTLE},
+    scene::{metronome::Metronome, midi_player::MidiPlayer, midi_recorder::MidiRecorder},
+    song::{
+        score::{NoteResult, Scorer},
+        Song, SongFile,
+    },
+    utils::dirs,
+    whistle::WhistleKey,
+};
 use midly::MidiMessage;
 use wgpu_jumpstart::{wgpu, TransformUniform, Uniform};
 use winit::{
@@ -10,24 +24,493 @@ use winit::{
 
 use super::Scene;
 
-use crate::render::SheetRenderer;
+use crate::render::{BeatRenderer, ProgressRenderer, SheetRenderer, SummaryRenderer};
+
+const SPEED_STEP: f32 = 0.05;
+const BPM_STEP: f32 = 5.0;
+/// Jump of shift and the left or right arrow.
+const SEEK_STEP: Duration = Duration::from_secs(5);
+/// Choices for the number of loop repetitions, `None` loops forever.
+const LOOP_REPEATS: [Option<u32>; 5] = [None, Some(2), Some(3), Some(5), Some(10)];
+/// Directory in the data directory with the recorded takes.
+const RECORDINGS_DIR: &str = "recordings";
 
 pub struct PlayingScene {
     pub sheet: SheetRenderer,
     pub player: MidiPlayer,
+    pub recorder: MidiRecorder,
+    song_name: String,
+    /// Start and end of the first note of the loop region, or the time picked
+    /// with `[`, waiting for the end of the region to be picked.
+    loop_start: Option<(Duration, Duration)>,
+    loop_repeats: Option<u32>,
+    loop_speed_step: f32,
+    loop_target_speed: f32,
+    /// Wait mode was halted at the last update.
+    waiting: bool,
+    score: Scorer,
+    /// The song is played to the end, and scored.
+    finished: bool,
+    /// The score of the finished song, shown instead of the sheet until the
+    /// playback jumps.
+    summary: Option<SummaryRenderer>,
+    history: History,
+    /// Shown instead of the sheet while set.
+    progress: Option<ProgressRenderer>,
+    metronome: Metronome,
+    beat: Option<BeatRenderer>,
+    /// Semitones the song is moved from how it is written.
+    transposed: i32,
 }
 
 impl PlayingScene {
-    pub fn new(ctx: &Context, song: Song) -> Self {
-        let sheet = SheetRenderer::new(&ctx.gpu, &ctx.transform);
+    pub fn new(ctx: &Context, song: Song) -> Result<Self, String> {
+        let instrument = ctx.instrument.clone();
+        let sheet = SheetRenderer::new(
+            &ctx.gpu,
+            &ctx.transform,
+            &song,
+            instrument.clone(),
+            ctx.whistle_key,
+            &ctx.config,
+        )
+        .or_else(|e| {
+            log::error!("{}", e);
+            SheetRenderer::plain(
+                &ctx.gpu,
+                &ctx.transform,
+                &song,
+                instrument,
+                ctx.whistle_key,
+                &ctx.config,
+            )
+        })?;
+        check_range(&song.file, &ctx.instrument, ctx.whistle_key);
+
+        let song_name = song.file.name.clone();
+        let mut player = MidiPlayer::new(song, ctx.output_connection.clone());
+        player.playback_mut().configure(&ctx.config);
+        let mut metronome = Metronome::new(
+            &player.playback().song().file,
+            ctx.output_connection.clone(),
+        );
+        metronome.set_enabled(ctx.config.metronome);
+        metronome.set_count_in_bars(ctx.config.count_in_bars);
+        metronome.start_count_in(Duration::ZERO, player.playback().speed());
+        let beat = BeatRenderer::new(&ctx.gpu, &ctx.transform)
+            .map_err(|e| log::error!("{}", e))
+            .ok();
+        let score = Scorer::new(
+            &player.playback().song().file,
+            ctx.config.timing_windows,
+            true,
+        );
+        Ok(Self {
+            sheet,
+            player,
+            recorder: MidiRecorder::new(),
+            song_name,
+            loop_start: None,
+            loop_repeats: None,
+            loop_speed_step: 0.0,
+            loop_target_speed: ctx.config.loop_target_speed,
+            waiting: false,
+            score,
+            finished: false,
+            summary: None,
+            history: History::load(),
+            progress: None,
+            metronome,
+            beat,
+            transposed: 0,
+        })
+    }
+
+    fn key_pressed(&mut self, ctx: &mut Context, key: &Key, repeat: bool) {
+        match key {
+            Key::Character(c) => match c.as_str() {
+                "r" if !repeat => self.toggle_recording(),
+                "h" if !repeat => self.toggle_progress(ctx),
+                "e" if !repeat => self.export_wav(),
+                "k" if !repeat => {
+                    ctx.set_whistle_key(ctx.whistle_key.next());
+                    log::info!("Playing the {}", ctx.whistle_key);
+                    self.instrument_changed(ctx);
+                }
+                "v" => self.sheet.next_alternate(),
+                "," => self.transpose(ctx, -1, 0),
+                "." => self.transpose(ctx, 1, 0),
+                "<" => self.transpose(ctx, 0, -1),
+                ">" => self.transpose(ctx, 0, 1),
+                "/" if !repeat => {
+                    let song = &self.player.playback().song().file;
+                    let semitones = ctx.instrument.fit(song, ctx.whistle_key);
+                    self.transpose(ctx, semitones, 0);
+                }
+                "i" if !repeat => {
+                    let instruments = Instrument::all();
+                    let index = instruments
+                        .iter()
+                        .position(|i| i.name == ctx.instrument.name)
+                        .map_or(0, |index| index + 1);
+                    if let Some(instrument) = instruments.into_iter().cycle().nth(index) {
+                        log::info!("Playing the {}", instrument.name);
+                        ctx.set_instrument(instrument);
+                        self.instrument_changed(ctx);
+                    }
+                }
+                "m" if !repeat => {
+                    let enabled = !self.metronome.is_enabled();
+                    self.metronome.set_enabled(enabled);
+                    if enabled && !self.metronome.is_enabled() {
+                        log::info!("{} has no tempo for the metronome", self.song_name);
+                    } else {
+                        log::info!("Metronome {}", if enabled { "on" } else { "off" });
+                    }
+                }
+                "c" if !repeat => {
+                    let bars = (self.metronome.count_in_bars() + 1) % (MAX_COUNT_IN_BARS + 1);
+                    self.metronome.set_count_in_bars(bars);
+                    log::info!("Count-in {} bars", bars);
+                }
+                "p" if !repeat => {
+                    let mut output = ctx.output_connection.clone();
+                    if output.program() == PROGRAM_WHISTLE {
+                        output.set_program(PROGRAM_FLUTE);
+                        log::info!("Playing the flute");
+                    } else {
+                        output.set_program(PROGRAM_WHISTLE);
+                        log::info!("Playing the whistle");
+                    }
+                }
+                "+" | "=" => self.change_speed(SPEED_STEP),
+                "-" => self.change_speed(-SPEED_STEP),
+                "0" => self.change_speed(0.0),
+                "[" => {
+                    let time = self.player.playback().time();
+                    self.loop_start = Some((time, time));
+                }
+                "]" => {
+                    let end = self.player.playback().time();
+                    if let Some((start, _)) = self.loop_start.take() {
+                        self.set_loop(start, end);
+                    }
+                }
+                "w" if !repeat => {
+                    let playback = self.player.playback_mut();
+                    let enabled = !playback.is_wait_mode();
+                    playback.set_wait_mode(enabled, true);
+                    log::info!("Wait mode {}", if enabled { "on" } else { "off" });
+                }
+                "l" => {
+                    self.loop_start = None;
+                    self.player.playback_mut().clear_loop();
+                    log::info!("Loop cleared");
+                }
+                "n" => {
+                    let index = LOOP_REPEATS
+                        .iter()
+                        .position(|&r| r == self.loop_repeats)
+                        .unwrap_or(0);
+                    self.loop_repeats = LOOP_REPEATS[(index + 1) % LOOP_REPEATS.len()];
+                    if let Some(region) = self.player.playback_mut().loop_region_mut() {
+                        region.repeats = self.loop_repeats;
+                    }
+                    match self.loop_repeats {
+                        Some(repeats) => log::info!("Loop {} times", repeats),
+                        None => log::info!("Loop until cleared"),
+                    }
+                }
+                "t" => {
+                    self.loop_speed_step = if self.loop_speed_step > 0.0 {
+                        0.0
+                    } else {
+                        SPEED_STEP
+                    };
+                    if let Some(region) = self.player.playback_mut().loop_region_mut() {
+                        region.speed_step = self.loop_speed_step;
+                    }
+                    log::info!(
+                        "Loop tempo increase {:.0}% per repetition, up to {:.0}%",
+                        self.loop_speed_step * 100.0,
+                        self.loop_target_speed * 100.0
+                    );
+                }
+                _ => {}
+            },
+            Key::Named(NamedKey::ArrowUp) => self.change_bpm(BPM_STEP),
+            Key::Named(NamedKey::ArrowDown) => self.change_bpm(-BPM_STEP),
+            Key::Named(NamedKey::Space) if !repeat => self.toggle_pause(),
+            Key::Named(NamedKey::Home) => {
+                self.player.restart();
+                self.jumped();
+                if !self.player.is_paused() {
+                    let speed = self.player.playback().speed();
+                    self.metronome.start_count_in(Duration::ZERO, speed);
+                }
+            }
+            Key::Named(NamedKey::ArrowLeft) if ctx.window_state.modifiers_state.shift_key() => {
+                let time = self.player.playback().time();
+                self.player.seek(time.saturating_sub(SEEK_STEP));
+                self.jumped();
+            }
+            Key::Named(NamedKey::ArrowRight) if ctx.window_state.modifiers_state.shift_key() => {
+                let time = self.player.playback().time();
+                self.player.seek(time + SEEK_STEP);
+                self.jumped();
+            }
+            Key::Named(NamedKey::ArrowLeft) => {
+                let bar = self.player.playback().bar();
+                self.player.seek_to_bar(bar.saturating_sub(1));
+                self.jumped();
+            }
+            Key::Named(NamedKey::ArrowRight) => {
+                let bar = self.player.playback().bar();
+                self.player.seek_to_bar(bar + 1);
+                self.jumped();
+            }
+            _ => {}
+        }
+    }
+
+    fn instrument_changed(&mut self, ctx: &Context) {
+        self.sheet
+            .set_instrument(ctx.instrument.clone(), ctx.whistle_key);
+        check_range(
+            &self.player.playback().song().file,
+            &ctx.instrument,
+            ctx.whistle_key,
+        );
+    }
+
+    /// Moves the notes of the song, the sheet keeps showing them as written.
+    pub fn transpose(&mut self, ctx: &Context, semitones: i8, octaves: i8) {
+        if semitones == 0 && octaves == 0 {
+            log::info!("The song fits the {}", ctx.instrument.name);
+            return;
+        }
+        if let Err(e) = self.player.transpose(semitones, octaves) {
+            log::warn!("{}", e);
+            return;
+        }
+        self.transposed += semitones as i32 + 12 * octaves as i32;
+        log::info!("Transposed by {} semitones", self.transposed);
+
+        // the scores are for the notes as they were
+        for notehead_id in self.score.reset_from(Duration::ZERO) {
+            self.sheet.note_result(notehead_id, None);
+        }
+        let playback = self.player.playback();
+        self.score = Scorer::new(&playback.song().file, self.score.windows(), true);
+        self.score.reset_from(playback.time());
+        self.instrument_changed(ctx);
+    }
+
+    /// Picks a loop point from a clicked note, the first click sets the start
+    /// of the loop and the second one its end.
+    pub fn loop_point(&mut self, start: Duration, end: Duration) {
+        match self.loop_start.take() {
+            None => {
+                log::info!("Loop start at {:.2}s", start.as_secs_f32());
+                self.loop_start = Some((start, end));
+            }
+            // either note may come first
+            Some(first) => self.set_loop(first.0.min(start), first.1.max(end)),
+        }
+    }
+
+    fn set_loop(&mut self, start: Duration, end: Duration) {
+        let playback = self.player.playback_mut();
+        playback.set_loop(start, end);
+        if let Some(region) = playback.loop_region_mut() {
+            region.repeats = self.loop_repeats;
+            region.speed_step = self.loop_speed_step;
+            region.target_speed = self.loop_target_speed;
+            log::info!(
+                "Loop from {:.2}s to {:.2}s",
+                start.as_secs_f32(),
+                end.as_secs_f32()
+            );
+        }
+        self.jumped();
+    }
+
+    /// Clears the noteheads and the score from the new position on, after
+    /// jumping to another position.
+    fn jumped(&mut self) {
+        self.sheet.reset_noteheads();
+        let time = self.player.playback().time();
+        for notehead_id in self.score.reset_from(time) {
+            self.sheet.note_result(notehead_id, None);
+        }
+        self.metronome.seek(time);
+        self.recorder.rewind(time);
+        self.finished = false;
+        self.summary = None;
+    }
+
+    fn toggle_progress(&mut self, ctx: &Context) {
+        if self.progress.take().is_some() {
+            return;
+        }
+        match ProgressRenderer::new(&ctx.gpu, &ctx.transform, &self.history, &self.song_name) {
+            Ok(progress) => self.progress = Some(progress),
+            Err(e) => log::error!("{}", e),
+        }
+    }
+
+    fn toggle_pause(&mut self) {
+        if self.player.is_paused() {
+            self.player.resume();
+            let playback = self.player.playback();
+            self.metronome
+                .start_count_in(playback.time(), playback.speed());
+        } else {
+            self.player.pause();
+            self.metronome.stop_count_in();
+        }
+    }
+
+    /// Plays at `bpm` quarter notes per minute, when the song has a tempo.
+    pub fn set_bpm(&mut self, bpm: f32) {
+        if !self.player.playback_mut().set_bpm(bpm) {
+            log::warn!("{} has no tempo to play at {} bpm", self.song_name, bpm);
+            return;
+        }
+        self.log_tempo();
+    }
+
+    /// Changes the tempo by `step` bpm, or the speed by a step for a song
+    /// without a tempo.
+    fn change_bpm(&mut self, step: f32) {
+        let playback = self.player.playback_mut();
+        let Some(bpm) = playback.bpm() else {
+            self.change_speed(SPEED_STEP.copysign(step));
+            return;
+        };
+        let bpm = ((bpm + step) / BPM_STEP).round() * BPM_STEP;
+        playback.set_bpm(bpm);
+        self.log_tempo();
+    }
+
+    /// Changes the tempo by `step`, a zero step resets it to the tempo of the song.
+    fn change_speed(&mut self, step: f32) {
+        let playback = self.player.playback_mut();
+        if step == 0.0 {
+            playback.set_speed(1.0);
+        } else {
+            // round to whole steps, so the percentage stays readable
+            let speed = ((playback.speed() + step) / SPEED_STEP).round() * SPEED_STEP;
+            playback.set_speed(speed);
+        }
+        self.log_tempo();
+    }
+
+    fn log_tempo(&self) {
+        let playback = self.player.playback();
+        match playback.bpm() {
+            Some(bpm) => log::info!("Tempo {:.0}% ({:.0} bpm)", playback.speed() * 100.0, bpm),
+            None => log::info!("Tempo {:.0}%", playback.speed() * 100.0),
+        }
+    }
+
+    fn toggle_recording(&mut self) {
+        if !self.recorder.is_recording() {
+            log::info!("Recording started");
+            self.recorder.start();
+            return;
+        }
+        self.recorder.stop();
+        if self.recorder.is_empty() {
+            log::info!("Recording stopped, nothing played");
+            return;
+        }
+        let stem = std::path::Path::new(&self.song_name)
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let secs = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let path = dirs::data_dir()
+            .join(RECORDINGS_DIR)
+            .join(format!("{}-{}.mid", stem, secs));
+        let info = &self.player.playback().song().file.info;
+        match self.recorder.save(&path, &self.song_name, info) {
+            Ok(()) => log::info!("Recording saved to {}", path.display()),
+            Err(e) => log::error!("{}", e),
+        }
+    }
 
-        let player = MidiPlayer::new(song);
-        Self { sheet, player }
+    /// Renders the song at the current tempo with the built-in synth to a WAV file.
+    fn export_wav(&self) {
+        let playback = self.player.playback();
+        let options = ExportOptions {
+            bpm: playback.bpm(),
+            ..Default::default()
+        };
+        let stem = std::path::Path::new(&self.song_name)
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let path = format!("{}.wav", stem);
+        match export::export_wav(&playback.song().file, &path, &options) {
+            Ok(()) => log::info!("Song written to {}", path),
+            Err(e) => log::error!("{}", e),
+        }
     }
 
     fn update_song_player(&mut self, ctx: &Context, delta: Duration) -> f32 {
+        if self.metronome.is_counting_in() {
+            // the playback starts after the count-in
+            self.metronome.count_in(delta);
+            return 0.0;
+        }
+        let playback = self.player.playback();
+        if let Some(region) = playback.loop_region().filter(|_| playback.loop_ended()) {
+            let successful = self.score.is_clean(region.start, region.end);
+            self.player.next_repetition(successful);
+            self.jumped();
+        }
         let events = self.player.update(delta);
         self.sheet.song_events(&events);
+
+        let playback = self.player.playback();
+        self.metronome.update(delta, playback.time());
+        for notehead_id in self.score.update(playback.time(), playback.speed()) {
+            self.sheet
+                .note_result(notehead_id, Some(NoteResult::Missed));
+        }
+        if playback.is_finished() && !self.finished {
+            self.finished = true;
+            let summary = self.score.summary();
+            log::info!("Score: {}", summary);
+            match SummaryRenderer::new(
+                &ctx.gpu,
+                &ctx.transform,
+                &summary,
+                self.score.windows(),
+                &self.song_name,
+            ) {
+                Ok(view) => self.summary = Some(view),
+                Err(e) => log::error!("{}", e),
+            }
+            let session = Session::new(&playback.song().file, playback.bpm(), &self.score);
+            if let Err(e) = self.history.add(session) {
+                log::error!("{}", e);
+            }
+        }
+
+        let waiting = self.player.playback().is_waiting();
+        if waiting && !self.waiting {
+            // show how to play the note the playback waits for
+            if let Some(note) = self.player.playback().waiting_for() {
+                let midi_key = note.midi_key;
+                self.sheet.show_fingering(midi_key);
+            }
+        }
+        self.waiting = waiting;
         0.0
     }
 }
@@ -37,6 +520,9 @@ impl Scene for PlayingScene {
         self.sheet.update_time(&mut ctx.gpu);
 
         let time = self.update_song_player(ctx, delta);
+        if let Some(beat) = self.beat.as_mut() {
+            beat.set_lit(&mut ctx.gpu, self.metronome.pulse());
+        }
     }
 
     fn render<'rpass>(
@@ -44,14 +530,89 @@ impl Scene for PlayingScene {
         transform_uniform: &'rpass Uniform<TransformUniform>,
         render_pass: &mut wgpu::RenderPass<'rpass>,
     ) {
-        self.sheet.render(transform_uniform, render_pass);
+        if let Some(progress) = &self.progress {
+            progress.render(transform_uniform, render_pass);
+        } else if let Some(summary) = &self.summary {
+            summary.render(transform_uniform, render_pass);
+        } else {
+            self.sheet.render(transform_uniform, render_pass);
+        }
+        if self.metronome.is_enabled() || self.metronome.is_counting_in() {
+            if let Some(beat) = &self.beat {
+                beat.render(transform_uniform, render_pass);
+            }
+        }
     }
 
     fn window_event(&mut self, ctx: &mut Context, event: &winit::event::WindowEvent) {
+        if let WindowEvent::KeyboardInput {
+            event:
+                KeyEvent {
+                    state: ElementState::Pressed,
+                    logical_key,
+                    repeat,
+                    ..
+                },
+            ..
+        } = event
+        {
+            self.key_pressed(ctx, logical_key, *repeat);
+        }
         SheetRenderer::handle_window_event(self, ctx, event);
     }
 
-    fn midi_event(&mut self, _ctx: &mut Context, _channel: u8, message: &MidiMessage) {
+    fn midi_event(&mut self, _ctx: &mut Context, channel: u8, message: &MidiMessage) {
+        let time = self.player.playback().time();
+        self.recorder.midi_event(time, channel, message);
+        if let MidiMessage::NoteOn { key, vel } = message {
+            if *vel > 0 {
+                let playback = self.player.playback();
+                let (time, speed) = (playback.time(), playback.speed());
+                if let Some((notehead_id, result)) = self.score.user_note(time, key.as_int(), speed)
+                {
+                    self.sheet.note_result(notehead_id, Some(result));
+                }
+                self.player.playback_mut().user_note(key.as_int());
+            }
+        }
         self.sheet.user_midi_event(&message);
     }
+
+    fn device_event(&mut self, _ctx: &mut Context, name: &str, connected: bool) {
+        if connected {
+            log::info!("Playing with {}", name);
+        } else if !self.player.is_paused() {
+            // nothing to play with, wait until it is back
+            self.player.pause();
+            log::info!("Paused, {} is gone", name);
+        }
+    }
+
+    /// Stops the sound, and saves the recording, when going back to the songs.
+    fn leave(&mut self, _ctx: &mut Context) {
+        if !self.player.is_paused() {
+            self.toggle_pause();
+        }
+        if self.recorder.is_recording() {
+            self.toggle_recording();
+        }
+    }
+}
+
+/// Warns about the notes of the song that the instrument can't play.
+fn check_range(song: &SongFile, instrument: &Instrument, whistle_key: WhistleKey) {
+    let out_of_range = song
+        .notes
+        .iter()
+        .filter(|note| instrument.fingerings(note.midi_key, whistle_key).is_empty())
+        .count();
+    if out_of_range > 0 {
+        log::warn!(
+            "{} notes of {} are out of range of the {} in {}",
+            out_of_range,
+            song.name,
+            instrument.name,
+            whistle_key.name()
+        );
+    }
 }
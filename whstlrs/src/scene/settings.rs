@@ -0,0 +1,355 @@
+use std::time::Duration;
+
+use resvg::usvg::{self, Color};
+use wgpu_jumpstart::{wgpu, TransformUniform, Uniform};
+use winit::event::{ElementState, KeyEvent, WindowEvent};
+use winit::keyboard::{Key, NamedKey};
+
+use super::Scene;
+use crate::config::{format_color, Config, MAX_COUNT_IN_BARS};
+use crate::context::Context;
+use crate::input_manager::InputConnection;
+use crate::instrument::Instrument;
+use crate::output_manager::{OutputConnection, PROGRAM_FLUTE, PROGRAM_WHISTLE};
+use crate::render::SettingsRenderer;
+use crate::song::score::TimingWindows;
+use crate::song::{MAX_SPEED, MIN_SPEED};
+use crate::whistle::WhistleKey;
+
+const SPEED_STEP: f32 = 0.05;
+const COLORS: [(&str, Color); 6] = [
+    ("Blue", rgb(0, 0, 255)),
+    ("Red", rgb(255, 0, 0)),
+    ("Green", rgb(0, 160, 0)),
+    ("Orange", rgb(255, 128, 0)),
+    ("Purple", rgb(128, 0, 192)),
+    ("Black", rgb(0, 0, 0)),
+];
+/// How strict the played notes are scored.
+const TIMING_WINDOWS: [(&str, TimingWindows); 3] = [
+    ("Relaxed", windows(120, 300)),
+    ("Normal", windows(80, 200)),
+    ("Strict", windows(50, 120)),
+];
+const WINDOW_SIZES: [(u32, u32); 4] = [(960, 540), (1280, 720), (1600, 900), (1920, 1080)];
+
+#[derive(Debug, Clone, Copy)]
+enum Setting {
+    Input,
+    Output,
+    Sound,
+    Whistle,
+    Instrument,
+    Speed,
+    LoopTarget,
+    WaitMode,
+    Metronome,
+    CountIn,
+    Timing,
+    NoteColor,
+    HoleColor,
+    WindowSize,
+}
+
+const SETTINGS: [Setting; 14] = [
+    Setting::Input,
+    Setting::Output,
+    Setting::Sound,
+    Setting::Whistle,
+    Setting::Instrument,
+    Setting::Speed,
+    Setting::LoopTarget,
+    Setting::WaitMode,
+    Setting::Metronome,
+    Setting::CountIn,
+    Setting::Timing,
+    Setting::NoteColor,
+    Setting::HoleColor,
+    Setting::WindowSize,
+];
+
+impl Setting {
+    fn name(&self) -> &'static str {
+        match self {
+            Setting::Input => "MIDI input",
+            Setting::Output => "MIDI output",
+            Setting::Sound => "Sound",
+            Setting::Whistle => "Whistle key",
+            Setting::Instrument => "Instrument",
+            Setting::Speed => "Speed",
+            Setting::LoopTarget => "Loops speed up to",
+            Setting::WaitMode => "Wait mode",
+            Setting::Metronome => "Metronome",
+            Setting::CountIn => "Count-in",
+            Setting::Timing => "Scoring",
+            Setting::NoteColor => "Note colour",
+            Setting::HoleColor => "Finger hole colour",
+            Setting::WindowSize => "Window size",
+        }
+    }
+}
+
+/// Edits the settings, they are saved and take effect when the scene is left.
+/// The speeds, wait mode, metronome, scoring and colours apply to the next song.
+pub struct SettingsScene {
+    config: Config,
+    selected: usize,
+    inputs: Vec<Option<String>>,
+    outputs: Vec<Option<String>>,
+    instruments: Vec<Option<String>>,
+    fontdb: usvg::fontdb::Database,
+    view: Option<SettingsRenderer>,
+    /// The settings have to be drawn again.
+    dirty: bool,
+}
+
+impl SettingsScene {
+    pub fn new(ctx: &Context) -> Self {
+        let ports = |names: Result<Vec<String>, String>| {
+            names
+                .unwrap_or_else(|e| {
+                    log::warn!("{}", e);
+                    Vec::new()
+                })
+                .into_iter()
+                .map(Some)
+                .collect::<Vec<_>>()
+        };
+        let inputs = [None]
+            .into_iter()
+            .chain(ports(InputConnection::port_names()));
+        let outputs = [None, Some("synth".to_string())]
+            .into_iter()
+            .chain(ports(OutputConnection::port_names()));
+        let instruments = [None]
+            .into_iter()
+            .chain(Instrument::all().into_iter().map(|i| Some(i.name)));
+        let mut fontdb = usvg::fontdb::Database::new();
+        fontdb.load_system_fonts();
+        Self {
+            config: ctx.config.clone(),
+            selected: 0,
+            inputs: inputs.collect(),
+            outputs: outputs.collect(),
+            instruments: instruments.collect(),
+            fontdb,
+            view: None,
+            dirty: true,
+        }
+    }
+
+    fn value(&self, setting: Setting) -> String {
+        let config = &self.config;
+        let on_off = |on: bool| if on { "On" } else { "Off" }.to_string();
+        match setting {
+            Setting::Input => config.input_port.clone().unwrap_or("Last used".to_string()),
+            Setting::Output => match config.output_port.as_deref() {
+                None => "First port".to_string(),
+                Some("synth") => "Built-in synth".to_string(),
+                Some(port) => port.to_string(),
+            },
+            Setting::Sound => match config.program {
+                PROGRAM_WHISTLE => "Whistle".to_string(),
+                PROGRAM_FLUTE => "Flute".to_string(),
+                program => format!("Program {}", program),
+            },
+            Setting::Whistle => config.whistle_key.to_string(),
+            Setting::Instrument => config
+                .instrument
+                .clone()
+                .unwrap_or(Instrument::default().name),
+            Setting::Speed => format!("{:.0}%", config.speed * 100.0),
+            Setting::LoopTarget => format!("{:.0}%", config.loop_target_speed * 100.0),
+            Setting::WaitMode => on_off(config.wait_mode),
+            Setting::Metronome => on_off(config.metronome),
+            Setting::CountIn => format!("{} bars", config.count_in_bars),
+            Setting::Timing => {
+                let windows = config.timing_windows;
+                let name = TIMING_WINDOWS
+                    .iter()
+                    .find(|(_, w)| *w == windows)
+                    .map_or("Custom", |(name, _)| name);
+                format!(
+                    "{}, hit within {} ms, early or late within {} ms",
+                    name,
+                    windows.hit.as_millis(),
+                    windows.ok.as_millis()
+                )
+            }
+            Setting::NoteColor => color_name(config.note_color),
+            Setting::HoleColor => color_name(config.hole_color),
+            Setting::WindowSize => format!("{} x {}", config.window_width, config.window_height),
+        }
+    }
+
+    /// Picks the next value of the selected setting, or the previous one for a
+    /// negative `step`.
+    fn change(&mut self, step: isize) {
+        let config = &mut self.config;
+        match SETTINGS[self.selected] {
+            Setting::Input => config.input_port = cycle(&self.inputs, &config.input_port, step),
+            Setting::Output => config.output_port = cycle(&self.outputs, &config.output_port, step),
+            Setting::Sound => {
+                config.program = cycle(&[PROGRAM_WHISTLE, PROGRAM_FLUTE], &config.program, step)
+            }
+            Setting::Whistle => {
+                config.whistle_key = cycle(&WhistleKey::ALL, &config.whistle_key, step)
+            }
+            Setting::Instrument => {
+                config.instrument = cycle(&self.instruments, &config.instrument, step)
+            }
+            Setting::Speed => config.speed = step_speed(config.speed, step),
+            Setting::LoopTarget => {
+                config.loop_target_speed = step_speed(config.loop_target_speed, step)
+            }
+            Setting::WaitMode => config.wait_mode = !config.wait_mode,
+            Setting::Metronome => config.metronome = !config.metronome,
+            Setting::CountIn => {
+                let choices: Vec<u32> = (0..=MAX_COUNT_IN_BARS).collect();
+                config.count_in_bars = cycle(&choices, &config.count_in_bars, step)
+            }
+            Setting::Timing => {
+                let choices = TIMING_WINDOWS.map(|(_, windows)| windows);
+                config.timing_windows = cycle(&choices, &config.timing_windows, step)
+            }
+            Setting::NoteColor => config.note_color = cycle_color(config.note_color, step),
+            Setting::HoleColor => config.hole_color = cycle_color(config.hole_color, step),
+            Setting::WindowSize => {
+                let size = (config.window_width, config.window_height);
+                (config.window_width, config.window_height) = cycle(&WINDOW_SIZES, &size, step);
+            }
+        }
+        self.dirty = true;
+    }
+
+    fn key_pressed(&mut self, key: &Key) {
+        match key {
+            Key::Named(NamedKey::ArrowUp) => {
+                self.selected = self.selected.saturating_sub(1);
+                self.dirty = true;
+            }
+            Key::Named(NamedKey::ArrowDown) => {
+                self.selected = (self.selected + 1).min(SETTINGS.len() - 1);
+                self.dirty = true;
+            }
+            Key::Named(NamedKey::ArrowLeft) => self.change(-1),
+            Key::Named(NamedKey::ArrowRight) | Key::Named(NamedKey::Enter) => self.change(1),
+            _ => {}
+        }
+    }
+}
+
+impl Scene for SettingsScene {
+    fn update(&mut self, ctx: &mut Context, _delta: Duration) {
+        if !self.dirty {
+            return;
+        }
+        self.dirty = false;
+        let rows: Vec<(&str, String)> = SETTINGS
+            .iter()
+            .map(|&setting| (setting.name(), self.value(setting)))
+            .collect();
+        match SettingsRenderer::new(&ctx.gpu, &ctx.transform, &self.fontdb, &rows, self.selected) {
+            Ok(view) => self.view = Some(view),
+            Err(e) => log::error!("{}", e),
+        }
+    }
+
+    fn render<'rpass>(
+        &'rpass mut self,
+        transform_uniform: &'rpass Uniform<TransformUniform>,
+        render_pass: &mut wgpu::RenderPass<'rpass>,
+    ) {
+        if let Some(view) = &self.view {
+            view.render(transform_uniform, render_pass);
+        }
+    }
+
+    fn window_event(&mut self, _ctx: &mut Context, event: &WindowEvent) {
+        if let WindowEvent::KeyboardInput {
+            event:
+                KeyEvent {
+                    state: ElementState::Pressed,
+                    logical_key,
+                    ..
+                },
+            ..
+        } = event
+        {
+            self.key_pressed(logical_key);
+        }
+    }
+
+    /// Applies the changed settings and saves them, the command line options
+    /// that weren't changed stay out of the file.
+    fn leave(&mut self, ctx: &mut Context) {
+        if self.config == ctx.config {
+            return;
+        }
+        let old = std::mem::replace(&mut ctx.config, self.config.clone());
+        let config = &self.config;
+        if config.input_port != old.input_port {
+            ctx.connect_input();
+        }
+        if config.output_port != old.output_port {
+            ctx.connect_output();
+        } else if config.program != old.program {
+            ctx.output_connection.set_program(config.program);
+        }
+        if config.whistle_key != old.whistle_key {
+            ctx.set_whistle_key(config.whistle_key);
+        }
+        if config.instrument != old.instrument {
+            let name = config.instrument.as_deref().unwrap_or_default();
+            ctx.set_instrument(Instrument::find(name).unwrap_or_default());
+        }
+        if (config.window_width, config.window_height) != (old.window_width, old.window_height) {
+            let size = winit::dpi::LogicalSize::new(config.window_width, config.window_height);
+            let _ = ctx.window.request_inner_size(size);
+        }
+        ctx.saved_config.apply_changes(&old, config);
+        match ctx.saved_config.save() {
+            Ok(()) => log::info!("Settings saved to {}", Config::path().display()),
+            Err(e) => log::error!("{}", e),
+        }
+    }
+}
+
+/// The choice `step` places from `current`, going round. A value that isn't
+/// among the choices goes to the first one.
+fn cycle<T: Clone + PartialEq>(choices: &[T], current: &T, step: isize) -> T {
+    let Some(index) = choices.iter().position(|choice| choice == current) else {
+        return choices[0].clone();
+    };
+    let index = (index as isize + step).rem_euclid(choices.len() as isize);
+    choices[index as usize].clone()
+}
+
+/// The speed `step` steps from `speed`, in whole steps.
+fn step_speed(speed: f32, step: isize) -> f32 {
+    let speed = speed + SPEED_STEP * step as f32;
+    ((speed / SPEED_STEP).round() * SPEED_STEP).clamp(MIN_SPEED, MAX_SPEED)
+}
+
+const fn rgb(red: u8, green: u8, blue: u8) -> Color {
+    Color { red, green, blue }
+}
+
+const fn windows(hit: u64, ok: u64) -> TimingWindows {
+    TimingWindows {
+        hit: Duration::from_millis(hit),
+        ok: Duration::from_millis(ok),
+    }
+}
+
+fn cycle_color(color: Color, step: isize) -> Color {
+    let colors = COLORS.map(|(_, color)| color);
+    cycle(&colors, &color, step)
+}
+
+fn color_name(color: Color) -> String {
+    COLORS
+        .iter()
+        .find(|(_, c)| *c == color)
+        .map_or_else(|| format_color(color), |(name, _)| name.to_string())
+}
@@ -0,0 +1,198 @@
+use std::time::Duration;
+
+use midly::MidiMessage;
+use resvg::usvg;
+use wgpu_jumpstart::{wgpu, TransformUniform, Uniform};
+use winit::event::{ElementState, KeyEvent, WindowEvent};
+use winit::keyboard::{Key, NamedKey};
+
+use super::{Scene, SceneRequest};
+use crate::context::Context;
+use crate::library::Library;
+use crate::render::LibraryRenderer;
+use crate::song::Song;
+use crate::WhstlrsEvent;
+
+/// Lists the songs in the library, to pick one to play.
+pub struct SongPicker {
+    library: Library,
+    /// The filter being typed, `None` while the typed keys play the whistle.
+    filter: Option<String>,
+    /// Indices of the entries that pass the filter.
+    shown: Vec<usize>,
+    /// Position of the selected song in `shown`.
+    selected: usize,
+    fontdb: usvg::fontdb::Database,
+    list: Option<LibraryRenderer>,
+    /// The list has to be drawn again.
+    dirty: bool,
+}
+
+impl SongPicker {
+    pub fn new(ctx: &Context) -> Self {
+        let dir = ctx.config.library_dir();
+        let library = Library::scan(&dir).unwrap_or_else(|e| {
+            log::error!("{}", e);
+            Library {
+                dir,
+                entries: Vec::new(),
+            }
+        });
+        let shown = library.filter("");
+        let mut fontdb = usvg::fontdb::Database::new();
+        fontdb.load_system_fonts();
+        Self {
+            library,
+            filter: None,
+            shown,
+            selected: 0,
+            fontdb,
+            list: None,
+            dirty: true,
+        }
+    }
+
+    fn select(&mut self, offset: isize) {
+        let last = self.shown.len().saturating_sub(1);
+        self.selected = self.selected.saturating_add_signed(offset).min(last);
+        self.dirty = true;
+    }
+
+    /// The list is filtered again in `update`, once for all the keys typed
+    /// since the last frame.
+    fn set_filter(&mut self, filter: Option<String>) {
+        self.filter = filter;
+        self.dirty = true;
+    }
+
+    fn push_filter(&mut self, text: &str) {
+        if let Some(filter) = &mut self.filter {
+            filter.push_str(text);
+            self.dirty = true;
+        }
+    }
+
+    /// Loads the selected song and asks to play it.
+    fn open(&self, ctx: &Context) {
+        let Some(&index) = self.shown.get(self.selected) else {
+            return;
+        };
+        let path = &self.library.entries[index].path;
+        match Song::load(path) {
+            Ok(song) => {
+                log::info!("Playing {}", path.display());
+                let _ = ctx
+                    .proxy
+                    .send_event(WhstlrsEvent::PushScene(SceneRequest::Playing(Box::new(
+                        song,
+                    ))));
+            }
+            Err(e) => log::error!("{}: {}", path.display(), e),
+        }
+    }
+
+    fn key_pressed(&mut self, ctx: &mut Context, key: &Key) {
+        match key {
+            Key::Named(NamedKey::ArrowUp) => self.select(-1),
+            Key::Named(NamedKey::ArrowDown) => self.select(1),
+            Key::Named(NamedKey::PageUp) => self.select(-(LibraryRenderer::ROWS as isize)),
+            Key::Named(NamedKey::PageDown) => self.select(LibraryRenderer::ROWS as isize),
+            Key::Named(NamedKey::Home) => self.select(isize::MIN),
+            Key::Named(NamedKey::End) => self.select(isize::MAX),
+            Key::Named(NamedKey::Enter) => self.open(ctx),
+            Key::Named(NamedKey::Tab) => {
+                let _ = ctx
+                    .proxy
+                    .send_event(WhstlrsEvent::PushScene(SceneRequest::Settings));
+            }
+            Key::Named(NamedKey::Backspace) => {
+                if let Some(filter) = &mut self.filter {
+                    // removing a letter from an empty filter stops filtering
+                    if filter.pop().is_none() {
+                        self.filter = None;
+                    }
+                    self.dirty = true;
+                }
+            }
+            Key::Named(NamedKey::Space) => self.push_filter(" "),
+            Key::Character(c) if self.filter.is_some() => self.push_filter(c),
+            Key::Character(c) if c.as_str() == "/" => self.set_filter(Some(String::new())),
+            _ => {}
+        }
+    }
+}
+
+impl Scene for SongPicker {
+    fn update(&mut self, ctx: &mut Context, _delta: Duration) {
+        if !self.dirty {
+            return;
+        }
+        self.dirty = false;
+        let shown = self
+            .library
+            .filter(self.filter.as_deref().unwrap_or_default());
+        if shown != self.shown {
+            self.shown = shown;
+            self.selected = 0;
+        }
+        match LibraryRenderer::new(
+            &ctx.gpu,
+            &ctx.transform,
+            &self.fontdb,
+            &self.library,
+            &self.shown,
+            self.filter.as_deref(),
+            self.selected,
+        ) {
+            Ok(list) => self.list = Some(list),
+            Err(e) => log::error!("{}", e),
+        }
+    }
+
+    fn render<'rpass>(
+        &'rpass mut self,
+        transform_uniform: &'rpass Uniform<TransformUniform>,
+        render_pass: &mut wgpu::RenderPass<'rpass>,
+    ) {
+        if let Some(list) = &self.list {
+            list.render(transform_uniform, render_pass);
+        }
+    }
+
+    fn window_event(&mut self, ctx: &mut Context, event: &WindowEvent) {
+        if let WindowEvent::KeyboardInput {
+            event:
+                KeyEvent {
+                    state: ElementState::Pressed,
+                    logical_key,
+                    ..
+                },
+            ..
+        } = event
+        {
+            self.key_pressed(ctx, logical_key);
+        }
+    }
+
+    /// The lowest note of the whistle opens the selected song, the next one up
+    /// moves down the list and the one after that moves up.
+    fn midi_event(&mut self, ctx: &mut Context, _channel: u8, message: &MidiMessage) {
+        let MidiMessage::NoteOn { key, vel } = message else {
+            return;
+        };
+        if *vel == 0 {
+            return;
+        }
+        let bottom = ctx.whistle_key.bottom() as i32;
+        match (key.as_int() as i32 - bottom).rem_euclid(12) {
+            0 => self.open(ctx),
+            2 => self.select(1),
+            4 => self.select(-1),
+            _ => {}
+        }
+    }
+
+    fn wants_text_input(&self) -> bool {
+        self.filter.is_some()
+    }
+}
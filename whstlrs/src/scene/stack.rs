@@ -0,0 +1,94 @@
+use std::time::Duration;
+
+use midly::MidiMessage;
+use wgpu_jumpstart::{wgpu, TransformUniform, Uniform};
+use winit::event::WindowEvent;
+
+use super::Scene;
+use crate::context::Context;
+
+/// The scenes on screen, the top one gets the events. A scene is covered by
+/// the one pushed on it, and comes back when that one is popped.
+#[derive(Default)]
+pub struct SceneStack {
+    scenes: Vec<Box<dyn Scene>>,
+}
+
+impl SceneStack {
+    pub fn is_empty(&self) -> bool {
+        self.scenes.is_empty()
+    }
+
+    pub fn push(&mut self, ctx: &mut Context, mut scene: Box<dyn Scene>) {
+        if let Some(top) = self.scenes.last_mut() {
+            top.leave(ctx);
+        }
+        scene.enter(ctx);
+        self.scenes.push(scene);
+    }
+
+    /// Removes the top scene, the one below it comes back.
+    pub fn pop(&mut self, ctx: &mut Context) -> Option<Box<dyn Scene>> {
+        let mut scene = self.scenes.pop()?;
+        scene.leave(ctx);
+        if let Some(top) = self.scenes.last_mut() {
+            top.enter(ctx);
+        }
+        Some(scene)
+    }
+
+    /// Puts `scene` in place of the top scene, without showing the one below.
+    pub fn replace(&mut self, ctx: &mut Context, mut scene: Box<dyn Scene>) {
+        if let Some(mut top) = self.scenes.pop() {
+            top.leave(ctx);
+        }
+        scene.enter(ctx);
+        self.scenes.push(scene);
+    }
+
+    pub fn wants_text_input(&self) -> bool {
+        self.scenes
+            .last()
+            .is_some_and(|scene| scene.wants_text_input())
+    }
+
+    pub fn update(&mut self, ctx: &mut Context, delta: Duration) {
+        if let Some(top) = self.scenes.last_mut() {
+            top.update(ctx, delta);
+        }
+    }
+
+    /// Draws the top scene, over the scenes below it while it is an overlay.
+    pub fn render<'pass>(
+        &'pass mut self,
+        transform: &'pass Uniform<TransformUniform>,
+        rpass: &mut wgpu::RenderPass<'pass>,
+    ) {
+        let bottom = self
+            .scenes
+            .iter()
+            .rposition(|scene| !scene.is_overlay())
+            .unwrap_or(0);
+        for scene in &mut self.scenes[bottom..] {
+            scene.render(transform, rpass);
+        }
+    }
+
+    pub fn window_event(&mut self, ctx: &mut Context, event: &WindowEvent) {
+        if let Some(top) = self.scenes.last_mut() {
+            top.window_event(ctx, event);
+        }
+    }
+
+    pub fn midi_event(&mut self, ctx: &mut Context, channel: u8, message: &MidiMessage) {
+        if let Some(top) = self.scenes.last_mut() {
+            top.midi_event(ctx, channel, message);
+        }
+    }
+
+    pub fn device_event(&mut self, ctx: &mut Context, name: &str, connected: bool) {
+        if let Some(top) = self.scenes.last_mut() {
+            top.device_event(ctx, name, connected);
+        }
+    }
+}
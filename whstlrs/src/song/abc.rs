@@ -0,0 +1,873 @@
+//! Importer for tunes in ABC notation, as found on https://thesession.org/
+//!
+//! Only the first tune of a file is read. Repeats and first/second endings are
+//! unfolded, so the resulting notes follow the order in which they are played.
+//! See https://abcnotation.com/wiki/abc:standard:v2.1
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use super::{note_value, SongFile, SongInfo, SongNote, TimeSignature, DEFAULT_TEMPO};
+
+/// Semitones above C for the note letters C D E F G A B.
+const NATURALS: [i32; 7] = [0, 2, 4, 5, 7, 9, 11];
+/// Position of the major key on the circle of fifths, for the note letters C D E F G A B.
+const FIFTHS: [i32; 7] = [0, 2, 4, -1, 1, 3, 5];
+/// Order in which sharps are added to a key signature, F C G D A E B.
+const SHARPS: [usize; 7] = [3, 0, 4, 1, 5, 2, 6];
+
+#[derive(Debug)]
+enum Element {
+    Note(Note),
+    Bar(Bar),
+    /// Seconds per whole note from here on.
+    Tempo(f32),
+}
+
+#[derive(Debug)]
+struct Note {
+    /// `None` for rests.
+    midi_key: Option<u8>,
+    /// In whole notes.
+    length: f32,
+    tie: bool,
+    notehead_id: String,
+}
+
+#[derive(Debug, Default)]
+struct Bar {
+    start_repeat: bool,
+    end_repeat: bool,
+    double: bool,
+    ending: Vec<u8>,
+}
+
+struct AbcParser {
+    info: SongInfo,
+    /// Default note length (L:) in whole notes.
+    unit: Option<f32>,
+    key_accidentals: [i32; 7],
+    bar_accidentals: HashMap<i32, i32>,
+    elements: Vec<Element>,
+    /// Notes left in the current tuplet and the factor applied to them.
+    tuplet: Option<(u32, f32)>,
+    /// Length factor for the next note from a broken rhythm (`>` or `<`).
+    broken: Option<f32>,
+}
+
+impl SongFile {
+    pub fn from_abc(text: &str, name: String) -> Result<Self, String> {
+        let mut parser = AbcParser::new();
+        let mut in_body = false;
+
+        for (line_nr, line) in text.lines().enumerate() {
+            let line = strip_comment(line);
+            let trimmed = line.trim();
+            if trimmed.is_empty() {
+                if in_body {
+                    // an empty line ends the tune
+                    break;
+                }
+                continue;
+            }
+            if let Some((field, value)) = split_field(trimmed) {
+                match field {
+                    // the next tune in the file
+                    'X' if in_body => break,
+                    'K' => {
+                        parser.field(field, value);
+                        in_body = true;
+                    }
+                    _ => parser.field(field, value),
+                }
+            } else if in_body {
+                parser.music_line(line, line_nr + 1)?;
+            }
+        }
+
+        if !in_body {
+            return Err(String::from("No ABC tune found"));
+        }
+        let (notes, bars) = parser.notes();
+        if notes.is_empty() {
+            return Err(String::from("ABC tune has no notes"));
+        }
+        let mut info = parser.info;
+        info.tempo.get_or_insert(DEFAULT_TEMPO);
+        let mut song = Self::from_notes(name, info, notes);
+        song.bars = bars;
+        Ok(song)
+    }
+}
+
+impl AbcParser {
+    fn new() -> Self {
+        Self {
+            info: SongInfo::default(),
+            unit: None,
+            key_accidentals: [0; 7],
+            bar_accidentals: HashMap::new(),
+            elements: Vec::new(),
+            tuplet: None,
+            broken: None,
+        }
+    }
+
+    fn unit(&self) -> f32 {
+        // default note length follows from the meter
+        self.unit.unwrap_or_else(|| match self.info.meter {
+            Some(m) if (m.numerator as f32 / m.denominator as f32) < 0.75 => 1.0 / 16.0,
+            _ => 1.0 / 8.0,
+        })
+    }
+
+    fn field(&mut self, field: char, value: &str) {
+        let value = value.trim();
+        match field {
+            'T' if self.info.title.is_none() => self.info.title = Some(value.to_string()),
+            'R' => self.info.rhythm = Some(value.to_string()),
+            'M' => self.info.meter = parse_meter(value),
+            'L' => {
+                if let Some(unit) = parse_fraction(value) {
+                    self.unit = Some(unit);
+                }
+            }
+            'Q' => {
+                if let Some(whole_secs) = parse_tempo(value, self.unit()) {
+                    self.info.tempo.get_or_insert(240.0 / whole_secs);
+                    self.elements.push(Element::Tempo(whole_secs));
+                }
+            }
+            'K' => {
+                if let Some(accidentals) = parse_key(value) {
+                    self.key_accidentals = accidentals;
+                    self.info.key.get_or_insert(value.to_string());
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn music_line(&mut self, line: &str, line_nr: usize) -> Result<(), String> {
+        let chars: Vec<char> = line.chars().collect();
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            match c {
+                '"' | '!' | '+' => {
+                    // chord symbols, annotations and decorations
+                    i = skip_past(&chars, i + 1, c);
+                }
+                '{' => {
+                    // grace notes
+                    i = skip_past(&chars, i + 1, '}');
+                }
+                '(' => {
+                    if chars.get(i + 1).is_some_and(|c| c.is_ascii_digit()) {
+                        i = self.tuplet(&chars, i + 1);
+                    } else {
+                        i += 1;
+                    }
+                }
+                '-' => {
+                    if let Some(note) = self.last_note_mut() {
+                        note.tie = true;
+                    }
+                    i += 1;
+                }
+                '>' | '<' => {
+                    let mut n = 0;
+                    while chars.get(i) == Some(&c) {
+                        n += 1;
+                        i += 1;
+                    }
+                    let short = 0.5f32.powi(n);
+                    let (prev, next) = if c == '>' {
+                        (2.0 - short, short)
+                    } else {
+                        (short, 2.0 - short)
+                    };
+                    if let Some(note) = self.last_note_mut() {
+                        note.length *= prev;
+                    }
+                    self.broken = Some(next);
+                }
+                '[' if chars.get(i + 1).is_some_and(|c| c.is_ascii_alphabetic())
+                    && chars.get(i + 2) == Some(&':') =>
+                {
+                    // inline field like [K:G]
+                    let end = skip_past(&chars, i + 1, ']');
+                    let field: String = chars[i + 1..end.saturating_sub(1)].iter().collect();
+                    if let Some((field, value)) = split_field(&field) {
+                        self.field(field, value);
+                    }
+                    i = end;
+                }
+                '[' if chars
+                    .get(i + 1)
+                    .is_some_and(|c| *c == '|' || c.is_ascii_digit()) =>
+                {
+                    i = self.bar(&chars, i);
+                }
+                '|' | ':' => {
+                    i = self.bar(&chars, i);
+                }
+                '[' => {
+                    i = self.chord(&chars, i + 1, line_nr)?;
+                }
+                '^' | '_' | '=' | 'A'..='G' | 'a'..='g' | 'z' | 'x' => {
+                    let (note, next) = self.note(&chars, i, line_nr)?;
+                    self.push_note(note);
+                    i = next;
+                }
+                'Z' | 'X' => {
+                    // multi-measure rest
+                    let (bars, next) = parse_number(&chars, i + 1);
+                    let bar_length = self
+                        .info
+                        .meter
+                        .map(|m| m.numerator as f32 / m.denominator as f32)
+                        .unwrap_or(1.0);
+                    self.elements.push(Element::Note(Note {
+                        midi_key: None,
+                        length: bars.unwrap_or(1) as f32 * bar_length,
+                        tie: false,
+                        notehead_id: String::new(),
+                    }));
+                    i = next;
+                }
+                _ => {
+                    // spaces, decorations, line continuations, ...
+                    i += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn last_note_mut(&mut self) -> Option<&mut Note> {
+        self.elements.iter_mut().rev().find_map(|e| match e {
+            Element::Note(note) => Some(note),
+            _ => None,
+        })
+    }
+
+    /// Parses a note or rest with its length, starting at the accidental or letter.
+    fn note(
+        &mut self,
+        chars: &[char],
+        start: usize,
+        line_nr: usize,
+    ) -> Result<(Note, usize), String> {
+        let mut i = start;
+        let mut accidental = None;
+        while let Some(&c) = chars.get(i) {
+            let delta = match c {
+                '^' => 1,
+                '_' => -1,
+                '=' => 0,
+                _ => break,
+            };
+            accidental = Some(accidental.unwrap_or(0) + delta);
+            i += 1;
+        }
+
+        let letter =
+            *chars
+                .get(i)
+                .ok_or(format!("Missing note at line {} column {}", line_nr, i + 1))?;
+        i += 1;
+        let midi_key = match letter {
+            'z' | 'x' => None,
+            'A'..='G' | 'a'..='g' => {
+                let index = letter_index(letter);
+                let mut natural =
+                    NATURALS[index] + if letter.is_ascii_lowercase() { 72 } else { 60 };
+                while let Some(&c) = chars.get(i) {
+                    match c {
+                        '\'' => natural += 12,
+                        ',' => natural -= 12,
+                        _ => break,
+                    }
+                    i += 1;
+                }
+                let accidental = match accidental {
+                    Some(a) => {
+                        // an accidental holds for the rest of the bar
+                        self.bar_accidentals.insert(natural, a);
+                        a
+                    }
+                    None => *self
+                        .bar_accidentals
+                        .get(&natural)
+                        .unwrap_or(&self.key_accidentals[index]),
+                };
+                Some((natural + accidental).clamp(0, 127) as u8)
+            }
+            _ => {
+                return Err(format!(
+                    "Unexpected '{}' at line {} column {}",
+                    letter, line_nr, i
+                ))
+            }
+        };
+        let (length, next) = parse_length(chars, i);
+
+        Ok((
+            Note {
+                midi_key,
+                length: length * self.unit(),
+                tie: false,
+                notehead_id: format!("Note-{}-{}", line_nr, start + 1),
+            },
+            next,
+        ))
+    }
+
+    /// Only the first note of a chord is kept, a whistle plays one note at a time.
+    fn chord(&mut self, chars: &[char], start: usize, line_nr: usize) -> Result<usize, String> {
+        let mut i = start;
+        let mut first: Option<Note> = None;
+        while let Some(&c) = chars.get(i) {
+            match c {
+                ']' => {
+                    i += 1;
+                    break;
+                }
+                '^' | '_' | '=' | 'A'..='G' | 'a'..='g' => {
+                    let (note, next) = self.note(chars, i, line_nr)?;
+                    first.get_or_insert(note);
+                    i = next;
+                }
+                '"' | '!' | '+' => i = skip_past(chars, i + 1, c),
+                _ => i += 1,
+            }
+        }
+        let (length, next) = parse_length(chars, i);
+        if let Some(mut note) = first {
+            note.length *= length;
+            self.push_note(note);
+        }
+        Ok(next)
+    }
+
+    fn push_note(&mut self, mut note: Note) {
+        if let Some(factor) = self.broken.take() {
+            note.length *= factor;
+        }
+        if let Some((remaining, factor)) = self.tuplet {
+            note.length *= factor;
+            self.tuplet = (remaining > 1).then_some((remaining - 1, factor));
+        }
+        self.elements.push(Element::Note(note));
+    }
+
+    /// Parses `(p:q:r`: put p notes into the time of q for the next r notes.
+    fn tuplet(&mut self, chars: &[char], start: usize) -> usize {
+        let (p, mut i) = parse_number(chars, start);
+        let p = p.unwrap_or(3).max(1);
+        let mut q = None;
+        let mut r = None;
+        if chars.get(i) == Some(&':') {
+            (q, i) = parse_number(chars, i + 1);
+            if chars.get(i) == Some(&':') {
+                (r, i) = parse_number(chars, i + 1);
+            }
+        }
+        let compound = self
+            .info
+            .meter
+            .is_some_and(|m| m.numerator % 3 == 0 && m.numerator > 3);
+        let q = q.unwrap_or(match p {
+            3 | 6 => 2,
+            2 | 4 | 8 => 3,
+            _ if compound => 3,
+            _ => 2,
+        });
+        self.tuplet = Some((r.unwrap_or(p), q as f32 / p as f32));
+        i
+    }
+
+    fn bar(&mut self, chars: &[char], start: usize) -> usize {
+        let mut i = start;
+        let mut token = String::new();
+        while let Some(&c) = chars.get(i) {
+            let part_of_bar = match c {
+                '|' | ':' | ']' => true,
+                '[' => chars
+                    .get(i + 1)
+                    .is_some_and(|c| *c == '|' || c.is_ascii_digit()),
+                _ => false,
+            };
+            if !part_of_bar {
+                break;
+            }
+            token.push(c);
+            i += 1;
+        }
+
+        let mut bar = Bar::default();
+        let pipes = token.find('|');
+        match pipes {
+            Some(first) => {
+                let last = token.rfind('|').unwrap();
+                bar.end_repeat = token[..first].contains(':');
+                bar.start_repeat = token[last..].contains(':');
+                bar.double = token.contains("||") || token.contains("|]") || token.contains("[|");
+            }
+            None if token.contains("::") => {
+                bar.end_repeat = true;
+                bar.start_repeat = true;
+            }
+            None => (),
+        }
+
+        // first and second endings, like |1 :|2 [1,3 or [1-2
+        if chars.get(i).is_some_and(|c| c.is_ascii_digit()) {
+            while let (Some(n), next) = parse_number(chars, i) {
+                let n = n.min(u8::MAX as u32) as u8;
+                if chars.get(next) == Some(&'-') {
+                    if let (Some(to), next) = parse_number(chars, next + 1) {
+                        bar.ending.extend(n..=to.min(u8::MAX as u32) as u8);
+                        i = next;
+                    } else {
+                        bar.ending.push(n);
+                        i = next + 1;
+                    }
+                } else {
+                    bar.ending.push(n);
+                    i = next;
+                }
+                if chars.get(i) == Some(&',') {
+                    i += 1;
+                } else {
+                    break;
+                }
+            }
+        }
+
+        self.bar_accidentals.clear();
+        self.elements.push(Element::Bar(bar));
+        i
+    }
+
+    /// Unfolds the repeats and converts the lengths to seconds, returns the
+    /// notes and the start of each bar.
+    fn notes(&self) -> (Vec<SongNote>, Vec<Duration>) {
+        let mut notes: Vec<SongNote> = Vec::new();
+        let mut bars = vec![Duration::ZERO];
+        let mut whole_secs = 240.0 / DEFAULT_TEMPO;
+        let mut time = 0.0;
+        let mut tied = false;
+
+        for element in unfold(&self.elements) {
+            match element {
+                Element::Tempo(secs) => whole_secs = *secs,
+                Element::Bar(_) => {
+                    let start = Duration::from_secs_f32(time);
+                    if bars.last().is_some_and(|&last| start > last) {
+                        bars.push(start);
+                    }
+                }
+                Element::Note(note) => {
+                    let duration_length = note.length * whole_secs;
+                    match (note.midi_key, notes.last_mut()) {
+                        (Some(midi_key), Some(prev)) if tied && prev.midi_key == midi_key => {
+                            prev.duration_length += duration_length;
+                        }
+                        (Some(midi_key), _) => notes.push(SongNote {
+                            timestamp: time,
+                            midi_key,
+                            duration: note_value(note.length),
+                            duration_length,
+                            notehead_id: note.notehead_id.clone(),
+                        }),
+                        (None, _) => (),
+                    }
+                    tied = note.tie && note.midi_key.is_some();
+                    time += duration_length;
+                }
+            }
+        }
+        // the closing bar line doesn't start a new bar
+        let end = Duration::from_secs_f32(time);
+        bars.retain(|&start| start < end || start.is_zero());
+        (notes, bars)
+    }
+}
+
+/// Plays the elements in order, taking repeats and endings into account.
+fn unfold(elements: &[Element]) -> Vec<&Element> {
+    let mut played = Vec::new();
+    let mut section_start = 0;
+    let mut pass = 1;
+    let mut skipping = false;
+    let mut in_ending = false;
+    let mut i = 0;
+    while i < elements.len() {
+        let element = &elements[i];
+        i += 1;
+        let bar = match element {
+            Element::Bar(bar) => bar,
+            _ => {
+                if !skipping {
+                    played.push(element);
+                }
+                continue;
+            }
+        };
+        played.push(element);
+
+        if skipping {
+            // an ending that isn't played this time lasts until the next ending or repeat
+            if bar.ending.is_empty() && !bar.end_repeat && !bar.double && !bar.start_repeat {
+                continue;
+            }
+            skipping = false;
+            if !bar.ending.is_empty() {
+                in_ending = bar.ending.contains(&pass);
+                skipping = !in_ending;
+                continue;
+            }
+            let next_is_ending =
+                matches!(elements.get(i), Some(Element::Bar(next)) if !next.ending.is_empty());
+            if !next_is_ending || bar.start_repeat {
+                pass = 1;
+                section_start = i;
+            }
+            continue;
+        }
+
+        if bar.end_repeat {
+            if pass == 1 {
+                pass = 2;
+                in_ending = false;
+                i = section_start;
+                continue;
+            }
+            pass = 1;
+            section_start = i;
+            in_ending = false;
+        }
+        if bar.start_repeat {
+            pass = 1;
+            section_start = i;
+            in_ending = false;
+        } else if bar.double && !bar.end_repeat {
+            if in_ending {
+                pass = 1;
+                in_ending = false;
+            }
+            if pass == 1 {
+                section_start = i;
+            }
+        }
+        if !bar.ending.is_empty() {
+            in_ending = bar.ending.contains(&pass);
+            skipping = !in_ending;
+        }
+    }
+    played
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut prev = ' ';
+    for (i, c) in line.char_indices() {
+        if c == '%' && prev != '\\' {
+            return &line[..i];
+        }
+        prev = c;
+    }
+    line
+}
+
+/// Splits `T: title` into the field letter and its value.
+fn split_field(line: &str) -> Option<(char, &str)> {
+    let mut chars = line.chars();
+    let field = chars.next()?;
+    // `A:|` would be a note followed by a repeat
+    if field.is_ascii_alphabetic()
+        && chars.next() == Some(':')
+        && !matches!(chars.next(), Some('|') | Some(':'))
+    {
+        Some((field, &line[2..]))
+    } else {
+        None
+    }
+}
+
+fn skip_past(chars: &[char], start: usize, end: char) -> usize {
+    chars[start.min(chars.len())..]
+        .iter()
+        .position(|&c| c == end)
+        .map(|p| start + p + 1)
+        .unwrap_or(chars.len())
+}
+
+fn parse_number(chars: &[char], start: usize) -> (Option<u32>, usize) {
+    let mut i = start;
+    let mut n: Option<u32> = None;
+    while let Some(d) = chars.get(i).and_then(|c| c.to_digit(10)) {
+        n = Some(n.unwrap_or(0).saturating_mul(10).saturating_add(d));
+        i += 1;
+    }
+    (n, i)
+}
+
+/// Parses a note length multiplier like `3`, `/`, `//`, `/4` or `3/2`.
+fn parse_length(chars: &[char], start: usize) -> (f32, usize) {
+    let (numerator, mut i) = parse_number(chars, start);
+    let mut denominator = 1;
+    while chars.get(i) == Some(&'/') {
+        let (d, next) = parse_number(chars, i + 1);
+        denominator *= d.unwrap_or(2).max(1);
+        i = next;
+    }
+    (numerator.unwrap_or(1) as f32 / denominator as f32, i)
+}
+
+fn parse_fraction(value: &str) -> Option<f32> {
+    let (n, d) = value.trim().split_once('/')?;
+    let n = n.trim().parse::<u32>().ok()?;
+    let d = d.trim().parse::<u32>().ok()?;
+    (d > 0).then(|| n as f32 / d as f32)
+}
+
+fn parse_meter(value: &str) -> Option<TimeSignature> {
+    match value.trim() {
+        "C" => Some(TimeSignature {
+            numerator: 4,
+            denominator: 4,
+        }),
+        "C|" => Some(TimeSignature {
+            numerator: 2,
+            denominator: 2,
+        }),
+        value => {
+            let (n, d) = value.split_once('/')?;
+            // complex meters like (2+3)/8
+            let numerator = n
+                .trim_matches(|c| c == '(' || c == ')' || c == ' ')
+                .split('+')
+                .map(|n| n.trim().parse::<u8>().ok())
+                .sum::<Option<u8>>()?;
+            let denominator = d.trim().parse::<u8>().ok()?;
+            Some(TimeSignature {
+                numerator,
+                denominator,
+            })
+        }
+    }
+}
+
+/// Parses `Q:1/4=120`, `Q:"Allegro" 3/8=80` or the old style `Q:120` in
+/// units of L, into seconds per whole note.
+fn parse_tempo(value: &str, unit: f32) -> Option<f32> {
+    let mut value = value.trim();
+    while let Some(rest) = value.strip_prefix('"') {
+        value = rest
+            .split_once('"')
+            .map(|(_, rest)| rest.trim())
+            .unwrap_or("");
+    }
+    let value = value.split('"').next()?.trim();
+    let (beat, bpm) = match value.split_once('=') {
+        Some((beats, bpm)) => {
+            let beat = beats
+                .split_whitespace()
+                .map(parse_fraction)
+                .sum::<Option<f32>>()?;
+            (beat, bpm.trim().parse::<f32>().ok()?)
+        }
+        None => (unit, value.parse::<f32>().ok()?),
+    };
+    (beat > 0.0 && bpm > 0.0).then(|| 60.0 / bpm / beat)
+}
+
+/// Returns the accidental of each note letter C D E F G A B for a key like
+/// `D`, `Emin`, `Ador`, `Bb mix` or `G ^c`.
+fn parse_key(value: &str) -> Option<[i32; 7]> {
+    let mut accidentals = [0; 7];
+    let mut tokens = value.split_whitespace().peekable();
+    let key = match tokens.peek() {
+        Some(token) if !token.contains('=') && !token.starts_with(['^', '_']) => tokens.next()?,
+        _ => "C",
+    };
+
+    let fifths = match key {
+        "none" => None,
+        "HP" => None,
+        "Hp" => Some(2),
+        _ => {
+            let mut chars = key.chars();
+            let tonic = chars.next()?.to_ascii_uppercase();
+            if !('A'..='G').contains(&tonic) {
+                return None;
+            }
+            let mut fifths = FIFTHS[letter_index(tonic)];
+            let mut mode = chars.as_str();
+            if let Some(rest) = mode.strip_prefix('#') {
+                fifths += 7;
+                mode = rest;
+            } else if let Some(rest) = mode.strip_prefix('b') {
+                fifths -= 7;
+                mode = rest;
+            }
+            let mode = if mode.is_empty() {
+                // the mode may follow as a separate word
+                match tokens.peek() {
+                    Some(t) if t.chars().all(|c| c.is_ascii_alphabetic()) => tokens.next()?,
+                    _ => "",
+                }
+            } else {
+                mode
+            };
+            let mode = mode.to_ascii_lowercase();
+            let offset = match mode.get(..3).unwrap_or(&mode) {
+                "" | "maj" | "ion" => 0,
+                "m" | "min" | "aeo" => -3,
+                "mix" => -1,
+                "dor" => -2,
+                "phr" => -4,
+                "lyd" => 1,
+                "loc" => -5,
+                _ => 0,
+            };
+            Some(fifths + offset)
+        }
+    };
+
+    if let Some(fifths) = fifths {
+        let fifths = fifths.clamp(-7, 7);
+        for &letter in SHARPS.iter().take(fifths.max(0) as usize) {
+            accidentals[letter] = 1;
+        }
+        for &letter in SHARPS.iter().rev().take((-fifths).max(0) as usize) {
+            accidentals[letter] = -1;
+        }
+    }
+
+    // explicit accidentals like `^f _b =c`
+    for token in tokens {
+        let letter = token.chars().last().unwrap_or(' ').to_ascii_uppercase();
+        if !('A'..='G').contains(&letter) {
+            continue;
+        }
+        accidentals[letter_index(letter)] = match &token[..token.len() - 1] {
+            "^" => 1,
+            "^^" => 2,
+            "_" => -1,
+            "__" => -2,
+            "=" => 0,
+            _ => continue,
+        };
+    }
+    Some(accidentals)
+}
+
+/// Index of a note letter in C D E F G A B.
+fn letter_index(letter: char) -> usize {
+    (letter.to_ascii_uppercase() as u8 + 7 - b'C') as usize % 7
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tune(body: &str) -> SongFile {
+        let text = format!("X:1\nT:Test\nM:4/4\nL:1/8\nK:D\n{}\n", body);
+        SongFile::from_abc(&text, "test.abc".to_string()).unwrap()
+    }
+
+    fn keys(song: &SongFile) -> Vec<u8> {
+        song.notes.iter().map(|note| note.midi_key).collect()
+    }
+
+    fn lengths(song: &SongFile) -> Vec<f32> {
+        song.notes.iter().map(|note| note.duration_length).collect()
+    }
+
+    #[test]
+    fn key_signature_and_accidentals() {
+        // F and c are sharp in D, =f holds until the bar line
+        let song = tune("DFAd =f f | f c'");
+        assert_eq!(keys(&song), [62, 66, 69, 74, 77, 77, 78, 85]);
+    }
+
+    #[test]
+    fn repeats_are_unfolded() {
+        let song = tune("|: AB :| d2 |]");
+        assert_eq!(keys(&song), [69, 71, 69, 71, 74]);
+    }
+
+    #[test]
+    fn endings_are_played_in_their_pass() {
+        let song = tune("|: A |1 B :|2 c |]");
+        assert_eq!(keys(&song), [69, 71, 69, 73]);
+        let timestamps: Vec<f32> = song.notes.iter().map(|note| note.timestamp).collect();
+        assert_eq!(timestamps, [0.0, 0.25, 0.5, 0.75]);
+    }
+
+    #[test]
+    fn broken_rhythm() {
+        // an eighth note lasts 0.25 seconds at the default tempo
+        let song = tune("A>B A<B A>>B");
+        assert_eq!(lengths(&song), [0.375, 0.125, 0.125, 0.375, 0.4375, 0.0625]);
+    }
+
+    #[test]
+    fn triplets_take_the_time_of_two_notes() {
+        let song = tune("(3ABc d");
+        let lengths = lengths(&song);
+        for length in &lengths[..3] {
+            assert!((length - 0.25 * 2.0 / 3.0).abs() < 1e-6);
+        }
+        assert_eq!(lengths[3], 0.25);
+        assert!((song.notes[3].timestamp - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn tied_notes_are_joined() {
+        let song = tune("A2-A2 B2");
+        assert_eq!(keys(&song), [69, 71]);
+        assert_eq!(lengths(&song), [1.0, 0.5]);
+    }
+
+    #[test]
+    fn meters() {
+        let meter = |value| parse_meter(value).map(|m| (m.numerator, m.denominator));
+        assert_eq!(meter("C"), Some((4, 4)));
+        assert_eq!(meter("C|"), Some((2, 2)));
+        assert_eq!(meter("6/8"), Some((6, 8)));
+        assert_eq!(meter("(2+3)/8"), Some((5, 8)));
+        assert_eq!(meter("free"), None);
+    }
+
+    #[test]
+    fn tempos() {
+        assert_eq!(parse_tempo("1/4=120", 0.125), Some(2.0));
+        assert_eq!(parse_tempo("\"Allegro\" 3/8=80", 0.125), Some(2.0));
+        assert_eq!(parse_tempo("240", 0.125), Some(2.0));
+        assert_eq!(parse_tempo("1/4=0", 0.125), None);
+    }
+
+    #[test]
+    fn modes() {
+        // A dorian and E minor have the key signature of G major
+        let g_major = [0, 0, 0, 1, 0, 0, 0];
+        assert_eq!(parse_key("Ador"), Some(g_major));
+        assert_eq!(parse_key("Em"), Some(g_major));
+        assert_eq!(parse_key("E minor"), Some(g_major));
+        assert_eq!(parse_key("Bb"), Some([0, 0, -1, 0, 0, 0, -1]));
+        assert_eq!(parse_key("D ^g"), Some([1, 0, 0, 1, 1, 0, 0]));
+        assert_eq!(parse_key("Hx"), None);
+    }
+
+    #[test]
+    fn only_the_first_tune_is_read() {
+        let text = "X:1\nK:G\nGAB\nX:2\nK:G\nDEF\n";
+        let song = SongFile::from_abc(text, "test.abc".to_string()).unwrap();
+        assert_eq!(keys(&song), [67, 69, 71]);
+        assert!(SongFile::from_abc("X:1\nT:No body\n", String::new()).is_err());
+    }
+}
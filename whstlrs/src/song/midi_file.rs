@@ -0,0 +1,524 @@
+//! Importer and exporter for Standard MIDI Files.
+//!
+//! Tick positions are converted to time with the tempo map of the whole file,
+//! running status is resolved by midly while parsing.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+
+use midly::{
+    num::{u15, u24, u28, u4},
+    Format, Header, MetaMessage, MidiMessage, Smf, Timing, TrackEvent, TrackEventKind,
+};
+
+use super::{note_value, SongFile, SongInfo, SongNote, TimeSignature, DEFAULT_TEMPO};
+
+/// Ticks per quarter note of the written files.
+const TICKS_PER_BEAT: u16 = 480;
+
+const MAJOR_KEYS: [&str; 15] = [
+    "Cb", "Gb", "Db", "Ab", "Eb", "Bb", "F", "C", "G", "D", "A", "E", "B", "F#", "C#",
+];
+const MINOR_KEYS: [&str; 15] = [
+    "Ab", "Eb", "Bb", "F", "C", "G", "D", "A", "E", "B", "F#", "C#", "G#", "D#", "A#",
+];
+
+/// The part of a MIDI file that holds the tune.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MidiSelection {
+    /// Track index, defaults to the first track with notes.
+    pub track: Option<usize>,
+    /// MIDI channel (0-15), defaults to all channels of the track, which are
+    /// merged into one part.
+    pub channel: Option<u8>,
+}
+
+/// Converts ticks to seconds, following the tempo changes in the file.
+struct TempoMap {
+    timing: Timing,
+    /// Tick of each tempo change with microseconds per beat from there on.
+    changes: Vec<(u64, u32)>,
+}
+
+impl TempoMap {
+    fn new(smf: &Smf) -> Self {
+        let mut changes: Vec<(u64, u32)> = smf
+            .tracks
+            .iter()
+            .flat_map(|track| {
+                let mut tick = 0u64;
+                track.iter().filter_map(move |event| {
+                    tick += event.delta.as_int() as u64;
+                    match event.kind {
+                        TrackEventKind::Meta(MetaMessage::Tempo(tempo)) => {
+                            Some((tick, tempo.as_int()))
+                        }
+                        _ => None,
+                    }
+                })
+            })
+            .collect();
+        changes.sort_by_key(|(tick, _)| *tick);
+        Self {
+            timing: smf.header.timing,
+            changes,
+        }
+    }
+
+    fn seconds(&self, tick: u64) -> f32 {
+        match self.timing {
+            Timing::Metrical(ticks_per_beat) => {
+                let ticks_per_beat = ticks_per_beat.as_int().max(1) as f64;
+                // the default tempo until the first tempo event
+                let mut tempo = 60_000_000.0 / DEFAULT_TEMPO as f64;
+                let mut last_tick = 0u64;
+                let mut micros = 0.0;
+                for &(change_tick, change_tempo) in self.changes.iter() {
+                    if change_tick >= tick {
+                        break;
+                    }
+                    micros += (change_tick - last_tick) as f64 * tempo / ticks_per_beat;
+                    last_tick = change_tick;
+                    tempo = change_tempo as f64;
+                }
+                micros += (tick - last_tick) as f64 * tempo / ticks_per_beat;
+                (micros / 1_000_000.0) as f32
+            }
+            Timing::Timecode(fps, ticks_per_frame) => {
+                tick as f32 / (fps.as_f32() * ticks_per_frame.max(1) as f32)
+            }
+        }
+    }
+
+    /// Length in whole notes, only known for metrical timing.
+    fn whole_notes(&self, ticks: u64) -> Option<f32> {
+        match self.timing {
+            Timing::Metrical(ticks_per_beat) => {
+                Some(ticks as f32 / (4.0 * ticks_per_beat.as_int().max(1) as f32))
+            }
+            Timing::Timecode(..) => None,
+        }
+    }
+}
+
+impl SongFile {
+    /// Reads the tune from the selected part of a MIDI file. The notes of all
+    /// the channels that are read play on channel 0, as a single part.
+    pub fn from_midi(data: &[u8], name: String, selection: MidiSelection) -> Result<Self, String> {
+        let smf = Smf::parse(data).map_err(|e| format!("Could not parse MIDI file: {}", e))?;
+        let tempo_map = TempoMap::new(&smf);
+
+        let has_notes = |track: &Vec<midly::TrackEvent>| {
+            track.iter().any(|event| match event.kind {
+                TrackEventKind::Midi {
+                    channel,
+                    message: MidiMessage::NoteOn { .. },
+                } => selection.channel.is_none_or(|c| c == channel.as_int()),
+                _ => false,
+            })
+        };
+        let track_index = match selection.track {
+            Some(index) if index < smf.tracks.len() => index,
+            Some(index) => {
+                return Err(format!(
+                    "Track {} not found, the file has {} tracks",
+                    index,
+                    smf.tracks.len()
+                ))
+            }
+            None => smf
+                .tracks
+                .iter()
+                .position(has_notes)
+                .ok_or(String::from("MIDI file has no notes"))?,
+        };
+
+        let info = Self::midi_info(&smf, &tempo_map);
+
+        let mut notes: Vec<SongNote> = Vec::new();
+        // notes still sounding, by channel and key, with their start tick
+        let mut sounding: HashMap<(u8, u8), (u64, usize)> = HashMap::new();
+        let mut tick = 0u64;
+        for event in smf.tracks[track_index].iter() {
+            tick += event.delta.as_int() as u64;
+            let (channel, message) = match event.kind {
+                TrackEventKind::Midi { channel, message } => (channel.as_int(), message),
+                _ => continue,
+            };
+            if selection.channel.is_some_and(|c| c != channel) {
+                continue;
+            }
+            match message {
+                MidiMessage::NoteOn { key, vel } if vel > 0 => {
+                    let key = key.as_int();
+                    if let Some(start) = sounding.remove(&(channel, key)) {
+                        // retriggered without a NoteOff
+                        Self::end_midi_note(&mut notes, &tempo_map, start, tick);
+                    }
+                    notes.push(SongNote {
+                        timestamp: tempo_map.seconds(tick),
+                        midi_key: key,
+                        duration: 0,
+                        duration_length: 0.0,
+                        notehead_id: format!("midi-{}-{}", track_index, notes.len()),
+                    });
+                    sounding.insert((channel, key), (tick, notes.len() - 1));
+                }
+                MidiMessage::NoteOn { key, .. } | MidiMessage::NoteOff { key, .. } => {
+                    if let Some(start) = sounding.remove(&(channel, key.as_int())) {
+                        Self::end_midi_note(&mut notes, &tempo_map, start, tick);
+                    }
+                }
+                _ => (),
+            }
+        }
+        for start in sounding.into_values() {
+            Self::end_midi_note(&mut notes, &tempo_map, start, tick);
+        }
+
+        if notes.is_empty() {
+            return Err(format!("Track {} has no notes", track_index));
+        }
+        let meter = info.meter;
+        let mut song = Self::from_notes(name, info, notes);
+        if let (Timing::Metrical(ticks_per_beat), Some(meter)) = (smf.header.timing, meter) {
+            // bars follow the tempo map, the beat is a quarter note
+            let ticks_per_bar = ticks_per_beat.as_int() as u64 * 4 * meter.numerator as u64
+                / meter.denominator.max(1) as u64;
+            if ticks_per_bar > 0 && tick > 0 {
+                song.bars = (0..tick)
+                    .step_by(ticks_per_bar as usize)
+                    .map(|tick| Duration::from_secs_f32(tempo_map.seconds(tick)))
+                    .collect();
+            }
+        }
+        Ok(song)
+    }
+
+    fn end_midi_note(notes: &mut [SongNote], tempo_map: &TempoMap, start: (u64, usize), tick: u64) {
+        let (start_tick, index) = start;
+        let note = &mut notes[index];
+        note.duration_length = tempo_map.seconds(tick) - note.timestamp;
+        note.duration = tempo_map
+            .whole_notes(tick - start_tick)
+            .map_or(4, note_value);
+    }
+
+    fn midi_info(smf: &Smf, tempo_map: &TempoMap) -> SongInfo {
+        let mut info = SongInfo {
+            tempo: Some(match tempo_map.changes.first() {
+                Some(&(_, tempo)) => 60_000_000.0 / tempo.max(1) as f32,
+                None => DEFAULT_TEMPO,
+            }),
+            ..Default::default()
+        };
+        for event in smf.tracks.iter().flatten() {
+            match event.kind {
+                TrackEventKind::Meta(MetaMessage::TrackName(name)) if info.title.is_none() => {
+                    let name = String::from_utf8_lossy(name).trim().to_string();
+                    if !name.is_empty() {
+                        info.title = Some(name);
+                    }
+                }
+                TrackEventKind::Meta(MetaMessage::TimeSignature(numerator, denominator, ..))
+                    if info.meter.is_none() =>
+                {
+                    info.meter = Some(TimeSignature {
+                        numerator,
+                        denominator: 1u8.checked_shl(denominator as u32).unwrap_or(4),
+                    });
+                }
+                TrackEventKind::Meta(MetaMessage::KeySignature(sharps, minor))
+                    if info.key.is_none() =>
+                {
+                    let index = (sharps.clamp(-7, 7) + 7) as usize;
+                    info.key = Some(if minor {
+                        format!("{}m", MINOR_KEYS[index])
+                    } else {
+                        MAJOR_KEYS[index].to_string()
+                    });
+                }
+                _ => (),
+            }
+        }
+        info
+    }
+
+    /// Writes the song as a single track Standard MIDI File, at its tempo.
+    pub fn save_midi<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let events: Vec<_> = self
+            .events
+            .iter()
+            .map(|event| (event.timestamp, event.channel, event.message))
+            .collect();
+        let title = self.info.title.as_deref().unwrap_or(&self.name);
+        write_midi(path, title, self.info.pace(), self.info.meter, &events)
+    }
+}
+
+/// Writes `events`, the time and channel of each message, as a single track
+/// Standard MIDI File with the tempo in quarter notes per minute and the meter.
+/// Events at the same time keep their order.
+pub fn write_midi<P: AsRef<Path>>(
+    path: P,
+    name: &str,
+    tempo: f32,
+    meter: Option<TimeSignature>,
+    events: &[(Duration, u8, MidiMessage)],
+) -> Result<(), String> {
+    let path = path.as_ref();
+    let micros_per_beat = (60_000_000.0 / tempo).round() as u32;
+    let ticks_per_second = TICKS_PER_BEAT as f64 * tempo as f64 / 60.0;
+    let mut track = vec![
+        TrackEvent {
+            delta: u28::new(0),
+            kind: TrackEventKind::Meta(MetaMessage::TrackName(name.as_bytes())),
+        },
+        TrackEvent {
+            delta: u28::new(0),
+            kind: TrackEventKind::Meta(MetaMessage::Tempo(u24::new(micros_per_beat))),
+        },
+    ];
+    if let Some(meter) = meter {
+        let denominator = meter.denominator.max(1).ilog2() as u8;
+        track.push(TrackEvent {
+            delta: u28::new(0),
+            kind: TrackEventKind::Meta(MetaMessage::TimeSignature(
+                meter.numerator,
+                denominator,
+                24,
+                8,
+            )),
+        });
+    }
+    let mut events: Vec<_> = events.iter().collect();
+    events.sort_by_key(|(time, ..)| *time);
+    let mut last_tick = 0u64;
+    for (time, channel, message) in events {
+        let tick = (time.as_secs_f64() * ticks_per_second).round() as u64;
+        track.push(TrackEvent {
+            delta: u28::new(
+                tick.saturating_sub(last_tick)
+                    .min(u28::max_value().as_int() as u64) as u32,
+            ),
+            kind: TrackEventKind::Midi {
+                channel: u4::new(*channel),
+                message: *message,
+            },
+        });
+        last_tick = tick;
+    }
+    track.push(TrackEvent {
+        delta: u28::new(0),
+        kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
+    });
+
+    let smf = Smf {
+        header: Header::new(
+            Format::SingleTrack,
+            Timing::Metrical(u15::new(TICKS_PER_BEAT)),
+        ),
+        tracks: vec![track],
+    };
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| format!("Could not create {}: {}", dir.display(), e))?;
+    }
+    smf.save(path)
+        .map_err(|e| format!("Could not write {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn on(delta: u32, channel: u8, key: u8, vel: u8) -> TrackEvent<'static> {
+        TrackEvent {
+            delta: u28::new(delta),
+            kind: TrackEventKind::Midi {
+                channel: u4::new(channel),
+                message: MidiMessage::NoteOn {
+                    key: key.into(),
+                    vel: vel.into(),
+                },
+            },
+        }
+    }
+
+    fn off(delta: u32, channel: u8, key: u8) -> TrackEvent<'static> {
+        TrackEvent {
+            delta: u28::new(delta),
+            kind: TrackEventKind::Midi {
+                channel: u4::new(channel),
+                message: MidiMessage::NoteOff {
+                    key: key.into(),
+                    vel: 0.into(),
+                },
+            },
+        }
+    }
+
+    fn meta(delta: u32, message: MetaMessage<'static>) -> TrackEvent<'static> {
+        TrackEvent {
+            delta: u28::new(delta),
+            kind: TrackEventKind::Meta(message),
+        }
+    }
+
+    fn read(
+        tracks: Vec<Vec<TrackEvent<'static>>>,
+        selection: MidiSelection,
+    ) -> Result<SongFile, String> {
+        let smf = Smf {
+            header: Header::new(Format::Parallel, Timing::Metrical(u15::new(480))),
+            tracks,
+        };
+        let mut data = Vec::new();
+        smf.write_std(&mut data).unwrap();
+        SongFile::from_midi(&data, "test.mid".to_string(), selection)
+    }
+
+    fn keys(song: &SongFile) -> Vec<u8> {
+        song.notes.iter().map(|note| note.midi_key).collect()
+    }
+
+    #[test]
+    fn ticks_follow_the_tempo_changes() {
+        // a beat is half a second until tick 960, then a quarter of a second
+        let tempo_track = vec![
+            meta(0, MetaMessage::Tempo(u24::new(500_000))),
+            meta(960, MetaMessage::Tempo(u24::new(250_000))),
+        ];
+        let notes = vec![
+            on(480, 0, 62, 100),
+            off(480, 0, 62),
+            on(480, 0, 64, 100),
+            off(480, 0, 64),
+        ];
+        let song = read(vec![tempo_track, notes], MidiSelection::default()).unwrap();
+        let timestamps: Vec<f32> = song.notes.iter().map(|note| note.timestamp).collect();
+        assert_eq!(timestamps, [0.5, 1.25]);
+        assert_eq!(song.notes[0].duration_length, 0.5);
+        assert_eq!(song.notes[1].duration_length, 0.25);
+        assert_eq!(song.notes[0].duration, 4);
+        assert_eq!(song.info.tempo, Some(120.0));
+    }
+
+    #[test]
+    fn note_on_without_velocity_ends_the_note() {
+        let notes = vec![
+            on(0, 0, 62, 100),
+            on(240, 0, 62, 0),
+            on(0, 0, 64, 100),
+            off(960, 0, 64),
+        ];
+        let song = read(vec![notes], MidiSelection::default()).unwrap();
+        assert_eq!(keys(&song), [62, 64]);
+        assert_eq!(song.notes[0].duration_length, 0.25);
+        assert_eq!(song.notes[0].duration, 8);
+        assert_eq!(song.notes[1].duration_length, 1.0);
+    }
+
+    #[test]
+    fn first_track_with_notes_on_all_channels() {
+        let info = vec![meta(0, MetaMessage::TrackName(b"Tune"))];
+        let first = vec![
+            on(0, 0, 62, 100),
+            off(480, 0, 62),
+            on(0, 1, 50, 100),
+            off(480, 1, 50),
+        ];
+        let second = vec![on(0, 2, 74, 100), off(480, 2, 74)];
+        let tracks = vec![info, first, second];
+
+        let song = read(tracks.clone(), MidiSelection::default()).unwrap();
+        assert_eq!(keys(&song), [62, 50]);
+        assert_eq!(song.info.title.as_deref(), Some("Tune"));
+        // the channels are merged into one part
+        assert!(song.events.iter().all(|event| event.channel == 0));
+
+        let channel = MidiSelection {
+            channel: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(keys(&read(tracks.clone(), channel).unwrap()), [50]);
+
+        let track = MidiSelection {
+            track: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(keys(&read(tracks.clone(), track).unwrap()), [74]);
+
+        // the first track with notes on the channel
+        let channel = MidiSelection {
+            channel: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(keys(&read(tracks, channel).unwrap()), [74]);
+    }
+
+    #[test]
+    fn missing_parts_are_an_error() {
+        let tracks = vec![vec![on(0, 0, 62, 100), off(480, 0, 62)]];
+        let track = MidiSelection {
+            track: Some(1),
+            ..Default::default()
+        };
+        assert!(read(tracks.clone(), track).is_err());
+        let channel = MidiSelection {
+            track: Some(0),
+            channel: Some(3),
+        };
+        assert!(read(tracks.clone(), channel).is_err());
+        let channel = MidiSelection {
+            channel: Some(3),
+            ..Default::default()
+        };
+        assert!(read(tracks, channel).is_err());
+        assert!(read(
+            vec![vec![meta(0, MetaMessage::EndOfTrack)]],
+            MidiSelection::default()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn written_file_reads_back() {
+        let note = |millis: u64, key: u8, vel: u8| {
+            let message = MidiMessage::NoteOn {
+                key: key.into(),
+                vel: vel.into(),
+            };
+            (Duration::from_millis(millis), 0, message)
+        };
+        // a take recorded over again from 1 s on, out of order
+        let events = [
+            note(0, 62, 100),
+            note(2000, 64, 0),
+            note(1000, 62, 0),
+            note(1000, 64, 100),
+        ];
+        let meter = TimeSignature {
+            numerator: 3,
+            denominator: 4,
+        };
+        let path = std::env::temp_dir().join(format!("whstlrs-{}-take.mid", std::process::id()));
+        write_midi(&path, "Take", 120.0, Some(meter), &events).unwrap();
+        let data = std::fs::read(&path);
+        std::fs::remove_file(&path).unwrap();
+        let song = SongFile::from_midi(
+            &data.unwrap(),
+            "take.mid".to_string(),
+            MidiSelection::default(),
+        )
+        .unwrap();
+        assert_eq!(keys(&song), [62, 64]);
+        let timestamps: Vec<f32> = song.notes.iter().map(|note| note.timestamp).collect();
+        assert_eq!(timestamps, [0.0, 1.0]);
+        assert_eq!(song.notes[1].duration_length, 1.0);
+        assert_eq!(song.info.title.as_deref(), Some("Take"));
+        assert_eq!(song.info.tempo, Some(120.0));
+        assert_eq!(song.info.meter, Some(meter));
+    }
+}
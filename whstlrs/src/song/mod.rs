@@ -0,0 +1,1076 @@
+mod abc;
+mod midi_file;
+pub mod score;
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::{fs, time::Duration};
+
+use midly::{num::u7, MidiMessage};
+
+use crate::config::Config;
+use crate::output_manager::DRUM_CHANNEL;
+
+pub use midi_file::{write_midi, MidiSelection};
+
+#[derive(Debug, Clone)]
+pub struct SongNote {
+    pub timestamp: f32,
+    pub midi_key: u8,
+    pub duration: u32,
+    pub duration_length: f32,
+    pub notehead_id: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct SongEvent {
+    pub channel: u8,
+    pub timestamp: Duration,
+    pub midi_key: u8,
+    pub duration_length: f32,
+    pub notehead_id: String,
+    pub message: MidiMessage,
+}
+
+/// Tempo used when the song file doesn't specify one, in quarter notes per minute.
+pub const DEFAULT_TEMPO: f32 = 120.0;
+/// Pace of the notes of songs without a tempo, like `.notes` files: a whole
+/// note a second, in quarter notes per minute.
+const UNTIMED_PACE: f32 = 240.0;
+/// Formats a `.notes` file takes its details from, in order of preference.
+const DETAIL_EXTENSIONS: [&str; 3] = ["abc", "mid", "midi"];
+pub const MIN_SPEED: f32 = 0.25;
+pub const MAX_SPEED: f32 = 2.0;
+
+/// A part of the song that is played over and over, for practice.
+#[derive(Debug, Clone)]
+pub struct LoopRegion {
+    pub start: Duration,
+    pub end: Duration,
+    /// Number of times to play the region, `None` loops until it is cleared.
+    pub repeats: Option<u32>,
+    /// Speed added after each successful repetition.
+    pub speed_step: f32,
+    /// The speed isn't increased beyond this.
+    pub target_speed: f32,
+    /// Repetitions played so far.
+    pub count: u32,
+}
+
+impl LoopRegion {
+    pub fn new(start: Duration, end: Duration) -> Self {
+        Self {
+            start,
+            end,
+            repeats: None,
+            speed_step: 0.0,
+            target_speed: 1.0,
+            count: 0,
+        }
+    }
+}
+
+/// Halts the playback at every note until the player plays it.
+struct WaitMode {
+    /// Indices of the song notes in playing order, one per moment.
+    notes: Vec<usize>,
+    /// Position in `notes` of the note to play next.
+    next: usize,
+    /// Accept the note in any octave.
+    octave_equivalence: bool,
+}
+
+pub struct PlaybackState {
+    song: Arc<Song>,
+    song_state: Box<SongState>,
+    running: Duration,
+    speed: f32,
+    paused: bool,
+    loop_region: Option<LoopRegion>,
+    wait: Option<WaitMode>,
+}
+struct SongState {
+    seen_events: usize,
+}
+
+impl PlaybackState {
+    pub fn new(song: Arc<Song>) -> Self {
+        PlaybackState {
+            song,
+            song_state: SongState { seen_events: 0 }.into(),
+            running: Duration::ZERO,
+            speed: 1.0,
+            paused: false,
+            loop_region: None,
+            wait: None,
+        }
+    }
+
+    /// Starts with the speed and wait mode of the config.
+    pub fn configure(&mut self, config: &Config) {
+        self.set_speed(config.speed);
+        self.set_wait_mode(config.wait_mode, true);
+    }
+
+    pub fn song(&self) -> &Song {
+        &self.song
+    }
+
+    /// Position in the song.
+    pub fn time(&self) -> Duration {
+        self.running
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.song_state.seen_events >= self.song.file.events.len()
+    }
+
+    /// Jumps to `time`, events from there on are played again by `update`.
+    pub fn seek(&mut self, time: Duration) {
+        self.running = time;
+        self.song_state.seen_events = self
+            .song
+            .file
+            .events
+            .partition_point(|event| event.timestamp < time);
+        if let Some(wait) = self.wait.as_mut() {
+            let notes = &self.song.file.notes;
+            wait.next = wait
+                .notes
+                .partition_point(|&i| Duration::from_secs_f32(notes[i].timestamp) < time);
+        }
+    }
+
+    pub fn is_wait_mode(&self) -> bool {
+        self.wait.is_some()
+    }
+
+    /// In wait mode the playback halts at each note until `user_note` gets it.
+    pub fn set_wait_mode(&mut self, enabled: bool, octave_equivalence: bool) {
+        if !enabled {
+            self.wait = None;
+            return;
+        }
+        self.wait = Some(WaitMode {
+            notes: self.song.file.playing_order(),
+            next: 0,
+            octave_equivalence,
+        });
+        self.seek(self.running);
+    }
+
+    /// The note the player has to play next in wait mode.
+    pub fn waiting_for(&self) -> Option<&SongNote> {
+        let wait = self.wait.as_ref()?;
+        wait.notes.get(wait.next).map(|&i| &self.song.file.notes[i])
+    }
+
+    /// The playback is halted, waiting for the player.
+    pub fn is_waiting(&self) -> bool {
+        self.waiting_for()
+            .is_some_and(|note| self.running >= Duration::from_secs_f32(note.timestamp))
+    }
+
+    /// Feeds a note played by the player to wait mode, returns true when it is
+    /// the note the playback is waiting for.
+    pub fn user_note(&mut self, midi_key: u8) -> bool {
+        let Some(note) = self.waiting_for() else {
+            return false;
+        };
+        let wait = self.wait.as_ref().unwrap();
+        let matches = note.midi_key == midi_key
+            || (wait.octave_equivalence && note.midi_key % 12 == midi_key % 12);
+        if matches {
+            self.wait.as_mut().unwrap().next += 1;
+        }
+        matches
+    }
+
+    /// Jumps to the start of a bar, counting from zero.
+    pub fn seek_to_bar(&mut self, bar: usize) {
+        let bars = &self.song.file.bars;
+        if let Some(&time) = bars.get(bar.min(bars.len().saturating_sub(1))) {
+            self.seek(time);
+        }
+    }
+
+    pub fn restart(&mut self) {
+        self.seek(Duration::ZERO);
+    }
+
+    pub fn loop_region(&self) -> Option<&LoopRegion> {
+        self.loop_region.as_ref()
+    }
+
+    pub fn loop_region_mut(&mut self) -> Option<&mut LoopRegion> {
+        self.loop_region.as_mut()
+    }
+
+    /// Loops between `start` and `end`, jumping to `start` when playing outside of it.
+    pub fn set_loop(&mut self, start: Duration, end: Duration) {
+        if end <= start {
+            return;
+        }
+        self.loop_region = Some(LoopRegion::new(start, end));
+        if self.running < start || self.running >= end {
+            self.seek(start);
+        }
+    }
+
+    pub fn clear_loop(&mut self) {
+        self.loop_region = None;
+    }
+
+    /// The end of the loop region is reached, see `next_repetition`.
+    pub fn loop_ended(&self) -> bool {
+        self.loop_region
+            .as_ref()
+            .is_some_and(|region| self.running >= region.end)
+    }
+
+    /// Starts the next repetition of the loop region, or ends the loop when all
+    /// repeats are played. A successful repetition raises the speed by the step
+    /// of the region.
+    pub fn next_repetition(&mut self, successful: bool) {
+        let Some(region) = self.loop_region.as_mut() else {
+            return;
+        };
+        region.count += 1;
+        if successful && region.speed_step > 0.0 && self.speed < region.target_speed {
+            self.speed = (self.speed + region.speed_step)
+                .min(region.target_speed)
+                .clamp(MIN_SPEED, MAX_SPEED);
+        }
+        let start = region.start;
+        if region
+            .repeats
+            .is_some_and(|repeats| region.count >= repeats)
+        {
+            self.loop_region = None;
+        } else {
+            self.seek(start);
+        }
+    }
+
+    /// Transposes the song, keeping the position and the other settings.
+    pub fn transpose(&mut self, semitones: i8, octaves: i8) -> Result<(), String> {
+        let mut song = self.song.as_ref().clone();
+        song.file.transpose(semitones, octaves)?;
+        self.song = Arc::new(song);
+        Ok(())
+    }
+
+    /// Index of the bar at the current position.
+    pub fn bar(&self) -> usize {
+        self.song.file.bar_at(self.running)
+    }
+
+    /// Tempo multiplier, 1.0 plays the song as written.
+    pub fn speed(&self) -> f32 {
+        self.speed
+    }
+
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = speed.clamp(MIN_SPEED, MAX_SPEED);
+    }
+
+    /// Tempo of the song as written, in quarter notes per minute, when it is known.
+    pub fn song_bpm(&self) -> Option<f32> {
+        self.song.file.info.tempo
+    }
+
+    /// Current tempo in quarter notes per minute.
+    pub fn bpm(&self) -> Option<f32> {
+        self.song_bpm().map(|bpm| bpm * self.speed)
+    }
+
+    /// Overrides the tempo with an absolute number of quarter notes per minute.
+    /// Returns false for a song without a tempo, which keeps its speed.
+    pub fn set_bpm(&mut self, bpm: f32) -> bool {
+        let Some(song_bpm) = self.song_bpm() else {
+            return false;
+        };
+        self.set_speed(bpm / song_bpm);
+        true
+    }
+
+    pub fn update(&mut self, delta: Duration) -> Vec<&SongEvent> {
+        if self.paused {
+            return Vec::new();
+        }
+        self.running += delta.mul_f32(self.speed);
+        let loop_end = self.loop_region.as_ref().map(|region| region.end);
+        let wait_at = self
+            .waiting_for()
+            .map(|note| Duration::from_secs_f32(note.timestamp));
+        if let Some(wait_at) = wait_at {
+            self.running = self.running.min(wait_at);
+        }
+        let events = self.song.file.events[self.song_state.seen_events..]
+            .iter()
+            .take_while(|event| event.timestamp <= self.running)
+            .take_while(|event| loop_end.is_none_or(|end| event.timestamp < end))
+            // a note ending at the halt is released, the next one waits
+            .take_while(|event| {
+                wait_at.is_none_or(|wait_at| {
+                    event.timestamp < wait_at
+                        || matches!(event.message, MidiMessage::NoteOff { .. })
+                })
+            })
+            .inspect(|_| self.song_state.seen_events += 1)
+            .collect();
+
+        events
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Song {
+    pub file: SongFile,
+    /// The sheet music, an SVG document with the ids of the notes in
+    /// `SongNote::notehead_id` and the finger holes as `fingerhole-1` and on.
+    pub svg: Option<Arc<Vec<u8>>>,
+    pub svg_path: Option<PathBuf>,
+}
+
+impl Song {
+    pub fn new(file: SongFile) -> Self {
+        Self {
+            file,
+            svg: None,
+            svg_path: None,
+        }
+    }
+
+    /// Loads a song file, with the sheet music next to it: the SVG file with
+    /// the same name.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        Self::load_with_selection(path, MidiSelection::default())
+    }
+
+    /// Like `load`, with the track and channel to read from a MIDI file.
+    pub fn load_with_selection<P: AsRef<Path>>(
+        path: P,
+        selection: MidiSelection,
+    ) -> Result<Self, String> {
+        let path = path.as_ref();
+        let song = Self::new(SongFile::with_selection(path, selection)?);
+        let svg_path = path.with_extension("svg");
+        if svg_path.is_file() {
+            song.with_svg(svg_path)
+        } else {
+            Ok(song)
+        }
+    }
+
+    pub fn with_svg<P: AsRef<Path>>(mut self, path: P) -> Result<Self, String> {
+        let path = path.as_ref();
+        let data =
+            fs::read(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        self.svg = Some(Arc::new(data));
+        self.svg_path = Some(path.to_path_buf());
+        Ok(self)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimeSignature {
+    pub numerator: u8,
+    pub denominator: u8,
+}
+
+/// Tune metadata, as far as the source format provides it.
+#[derive(Debug, Clone, Default)]
+pub struct SongInfo {
+    pub title: Option<String>,
+    pub rhythm: Option<String>,
+    pub key: Option<String>,
+    pub meter: Option<TimeSignature>,
+    /// Quarter notes per minute
+    pub tempo: Option<f32>,
+}
+
+impl SongInfo {
+    /// Quarter notes per minute the notes are timed at: the tempo, or a whole
+    /// note a second without one.
+    pub fn pace(&self) -> f32 {
+        self.tempo.unwrap_or(UNTIMED_PACE)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SongFile {
+    pub name: String,
+    pub info: SongInfo,
+    pub notes: Vec<SongNote>,
+    pub events: Vec<SongEvent>,
+    /// Start of each bar.
+    pub bars: Vec<Duration>,
+}
+
+impl SongFile {
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        Self::with_selection(path, MidiSelection::default())
+    }
+
+    /// Like `new`, with the track and channel to read from a MIDI file.
+    pub fn with_selection<P: AsRef<Path>>(
+        path: P,
+        selection: MidiSelection,
+    ) -> Result<Self, String> {
+        let name = path
+            .as_ref()
+            .file_name()
+            .ok_or(String::from("File not found"))?
+            .to_string_lossy()
+            .to_string();
+
+        let extension = path
+            .as_ref()
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase());
+        if let Some("mid" | "midi") = extension.as_deref() {
+            let data = fs::read(&path).map_err(|_| String::from("Could Not Open File"))?;
+            return Self::from_midi(&data, name, selection);
+        }
+
+        let text: String = match fs::read_to_string(&path) {
+            Ok(buff) => buff,
+            Err(_) => return Err(String::from("Could Not Open File")),
+        };
+
+        match extension.as_deref() {
+            Some("abc") => Self::from_abc(&text, name),
+            _ => Self::from_str(&text, name).map(|song| song.with_details_of(path.as_ref())),
+        }
+    }
+
+    /// Takes the details a `.notes` file lacks, the title, key, meter and
+    /// tempo, from the song next to it in another format, like the `.abc` file
+    /// of a LilyPond export. The song stays without them when there is none.
+    fn with_details_of(self, path: &Path) -> Self {
+        let other = DETAIL_EXTENSIONS
+            .iter()
+            .map(|extension| path.with_extension(extension))
+            .filter(|path| path.is_file())
+            .find_map(|path| {
+                Self::new(&path)
+                    .map_err(|e| log::warn!("{}: {}", path.display(), e))
+                    .ok()
+            });
+        let Some(other) = other else {
+            return self;
+        };
+        let same_notes = other.playing_order().len() == self.playing_order().len();
+        let mut song = self.with_info(other.info);
+        if same_notes {
+            // the bars of the other format know about a pickup
+            song.bars = other.bars;
+        }
+        song
+    }
+
+    /// The song with the details of `info`, the notes moved to its tempo.
+    fn with_info(self, info: SongInfo) -> Self {
+        let scale = self.info.pace() / info.pace();
+        let notes = self
+            .notes
+            .into_iter()
+            .map(|note| SongNote {
+                timestamp: note.timestamp * scale,
+                duration_length: note.duration_length * scale,
+                ..note
+            })
+            .collect();
+        Self::from_notes(self.name, info, notes)
+    }
+
+    /// Builds a song from its notes, deriving the NoteOn/NoteOff events.
+    pub fn from_notes(name: String, info: SongInfo, notes: Vec<SongNote>) -> Self {
+        let mut events: Vec<SongEvent> = Vec::new();
+        for note in notes.iter() {
+            let timestamp_on = Duration::from_secs_f32(note.timestamp);
+            let timestamp_off = Duration::from_secs_f32(note.timestamp + note.duration_length);
+            events.push(SongEvent {
+                channel: 0,
+                timestamp: timestamp_on,
+                message: MidiMessage::NoteOn {
+                    key: u7::new(note.midi_key),
+                    vel: u7::new(127),
+                },
+                midi_key: note.midi_key,
+                duration_length: note.duration_length,
+                notehead_id: note.notehead_id.to_string(),
+            });
+            events.push(SongEvent {
+                channel: 0,
+                timestamp: timestamp_off,
+                message: MidiMessage::NoteOff {
+                    key: u7::new(note.midi_key),
+                    vel: u7::new(0),
+                },
+                midi_key: note.midi_key,
+                duration_length: note.duration_length,
+                notehead_id: note.notehead_id.to_string(),
+            });
+        }
+        // stable, so a NoteOff stays in front of the NoteOn at the same moment
+        events.sort_by_key(|event| event.timestamp);
+        let end = events
+            .last()
+            .map_or(Duration::ZERO, |event| event.timestamp);
+        let bars = Self::meter_bars(&info, end);
+        Self {
+            name,
+            info,
+            notes,
+            events,
+            bars,
+        }
+    }
+
+    /// Bars of equal length following the meter, 4/4 when the meter is unknown.
+    fn meter_bars(info: &SongInfo, end: Duration) -> Vec<Duration> {
+        let meter = info.meter.unwrap_or(TimeSignature {
+            numerator: 4,
+            denominator: 4,
+        });
+        let whole_secs = 240.0 / info.pace();
+        let bar_secs = meter.numerator as f32 / meter.denominator.max(1) as f32 * whole_secs;
+        let bar_length = Duration::from_secs_f32(bar_secs.max(0.01));
+        let mut bars = vec![Duration::ZERO];
+        while *bars.last().unwrap() + bar_length < end {
+            bars.push(*bars.last().unwrap() + bar_length);
+        }
+        bars
+    }
+
+    /// Beats following the meter, true for the first beat of a bar. A song
+    /// without a tempo has none.
+    pub fn beats(&self) -> Vec<(Duration, bool)> {
+        if self.info.tempo.is_none() {
+            return Vec::new();
+        }
+        let per_bar = self.info.meter.map_or(4, |m| m.numerator.max(1)) as u32;
+        let end = self.events.last().map_or(Duration::ZERO, |e| e.timestamp);
+        let mut bars = self.bars.clone();
+        // close the last bar, as long as the one before
+        let last = bars.windows(2).last().map(|w| w[1] - w[0]);
+        if let (Some(&start), Some(length)) = (bars.last(), last) {
+            bars.push(start + length);
+        } else if let Some(&start) = bars.last() {
+            bars.push(end.max(start + Duration::from_millis(1)));
+        }
+
+        let mut beats = Vec::new();
+        for (i, bar) in bars.windows(2).enumerate() {
+            let length = bar[1] - bar[0];
+            // a pickup bar counts back from the first full bar
+            let full = match bars.get(i + 2) {
+                Some(&next_end) if i == 0 => next_end - bar[1],
+                _ => length,
+            };
+            let beat = full / per_bar;
+            if length < full.mul_f32(0.99) {
+                let mut pickup: Vec<(Duration, bool)> = (1..=per_bar)
+                    .map_while(|k| bar[1].checked_sub(beat * k))
+                    .filter(|&time| time + Duration::from_millis(1) >= bar[0])
+                    .map(|time| (time, false))
+                    .collect();
+                pickup.reverse();
+                beats.extend(pickup);
+            } else {
+                beats.extend((0..per_bar).map(|k| (bar[0] + beat * k, k == 0)));
+            }
+        }
+        beats
+    }
+
+    /// Moves all notes by `semitones` and `octaves`, up or down when negative.
+    /// Fails without changes when a note would go out of the MIDI range.
+    pub fn transpose(&mut self, semitones: i8, octaves: i8) -> Result<(), String> {
+        let shift = semitones as i32 + 12 * octaves as i32;
+        let moved = |key: u8| {
+            u8::try_from(key as i32 + shift)
+                .ok()
+                .filter(|&key| key < 128)
+        };
+        let fits = self.notes.iter().all(|note| moved(note.midi_key).is_some())
+            && self
+                .events
+                .iter()
+                .filter(|event| event.channel != DRUM_CHANNEL)
+                .all(|event| {
+                    moved(event.midi_key).is_some()
+                        && moved_message(&event.message, &moved).is_some()
+                });
+        if !fits {
+            return Err(format!(
+                "Can't transpose {} by {} semitones",
+                self.name, shift
+            ));
+        }
+
+        for note in self.notes.iter_mut() {
+            note.midi_key = moved(note.midi_key).unwrap();
+        }
+        for event in self.events.iter_mut() {
+            // the percussion channel plays instruments, not pitches
+            if event.channel == DRUM_CHANNEL {
+                continue;
+            }
+            event.midi_key = moved(event.midi_key).unwrap();
+            event.message = moved_message(&event.message, &moved).unwrap();
+        }
+        if let Some(key) = self.info.key.as_deref() {
+            self.info.key = transpose_key_name(key, shift);
+        }
+        Ok(())
+    }
+
+    /// Index of the bar that contains `time`.
+    pub fn bar_at(&self, time: Duration) -> usize {
+        self.bars
+            .partition_point(|&start| start <= time)
+            .saturating_sub(1)
+    }
+
+    /// Indices of the notes in the order they are played.
+    pub fn playing_order(&self) -> Vec<usize> {
+        let notes = &self.notes;
+        let mut order: Vec<usize> = (0..notes.len()).collect();
+        order.sort_by(|&a, &b| notes[a].timestamp.total_cmp(&notes[b].timestamp));
+        // notes of several staves at the same moment are played once
+        order.dedup_by(|a, b| notes[*a].timestamp == notes[*b].timestamp);
+        order
+    }
+
+    /// Reads the notes of a `.notes` file, timed in whole notes. They play at
+    /// a whole note a second, the file has no tempo or meter.
+    pub fn from_str(text: &str, name: String) -> Result<Self, String> {
+        // maybe parse using nom?
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .delimiter(b'\t')
+            .from_reader(text.as_bytes());
+        let mut notes: Vec<SongNote> = Vec::new();
+        for record in reader.records() {
+            let record = record.map_err(|e| e.to_string())?;
+            if record.get(1) != Some("note") {
+                continue;
+            }
+            let line = record.position().map_or(0, |p| p.line());
+            let field = |i: usize, name: &str| {
+                record
+                    .get(i)
+                    .ok_or_else(|| format!("Missing {} at line {}", name, line))
+            };
+            let number = |i: usize, name: &str| {
+                let value = field(i, name)?;
+                value
+                    .trim()
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|n| n.is_finite() && *n >= 0.0)
+                    .ok_or_else(|| format!("Invalid {} {:?} at line {}", name, value, line))
+            };
+            let timestamp = number(0, "timestamp")?;
+            let midi_key = field(2, "pitch")?
+                .trim()
+                .parse::<u8>()
+                .ok()
+                .filter(|&key| key < 128)
+                .ok_or_else(|| format!("Invalid pitch at line {}", line))?;
+            let duration = field(3, "duration")?
+                .trim()
+                .parse::<u32>()
+                .map_err(|_| format!("Invalid duration at line {}", line))?;
+            let duration_length = number(4, "duration length")?;
+            let notehead_id = field(5, "notehead id")?.to_string();
+
+            notes.push(SongNote {
+                timestamp,
+                midi_key,
+                duration,
+                duration_length,
+                notehead_id,
+            });
+        }
+        Ok(Self::from_notes(name, SongInfo::default(), notes))
+    }
+
+    /// Writes the notes in the format of `from_str`, with the timestamps and
+    /// lengths in whole notes. The file has no tempo, title, key or meter, it
+    /// plays at a whole note a second unless there is a song next to it to
+    /// take them from.
+    pub fn save_notes<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let path = path.as_ref();
+        let write_error =
+            |e: &dyn std::fmt::Display| format!("Could not write {}: {}", path.display(), e);
+        let whole_notes = self.info.pace() / UNTIMED_PACE;
+        let mut writer = csv::WriterBuilder::new()
+            .has_headers(false)
+            .delimiter(b'\t')
+            .from_path(path)
+            .map_err(|e| write_error(&e))?;
+        for note in self.notes.iter() {
+            writer
+                .write_record([
+                    format!("{:.8}", note.timestamp * whole_notes),
+                    "note".to_string(),
+                    note.midi_key.to_string(),
+                    note.duration.to_string(),
+                    format!("{:.8}", note.duration_length * whole_notes),
+                    note.notehead_id.clone(),
+                ])
+                .map_err(|e| write_error(&e))?;
+        }
+        writer.flush().map_err(|e| write_error(&e))
+    }
+}
+
+/// The message with its key moved, `None` when the key goes out of range.
+fn moved_message(message: &MidiMessage, moved: &impl Fn(u8) -> Option<u8>) -> Option<MidiMessage> {
+    let key = |key: u7| moved(key.as_int()).map(u7::new);
+    Some(match *message {
+        MidiMessage::NoteOn { key: k, vel } => MidiMessage::NoteOn { key: key(k)?, vel },
+        MidiMessage::NoteOff { key: k, vel } => MidiMessage::NoteOff { key: key(k)?, vel },
+        MidiMessage::Aftertouch { key: k, vel } => MidiMessage::Aftertouch { key: key(k)?, vel },
+        message => message,
+    })
+}
+
+/// A key like "D", "Ador" or "F#m" moved by `semitones`, the mode is kept.
+fn transpose_key_name(key: &str, semitones: i32) -> Option<String> {
+    const TONICS: [&str; 12] = [
+        "C", "Db", "D", "Eb", "E", "F", "F#", "G", "Ab", "A", "Bb", "B",
+    ];
+    let mut chars = key.trim().chars();
+    let tonic: i32 = match chars.next()?.to_ascii_uppercase() {
+        'C' => 0,
+        'D' => 2,
+        'E' => 4,
+        'F' => 5,
+        'G' => 7,
+        'A' => 9,
+        'B' => 11,
+        // no key, like "none" or "HP" for the highland pipes
+        _ => return Some(key.to_string()),
+    };
+    let rest = chars.as_str();
+    let (alter, mode) = match rest.chars().next() {
+        Some('#') => (1, &rest[1..]),
+        Some('b') => (-1, &rest[1..]),
+        _ => (0, rest),
+    };
+    let tonic = (tonic + alter + semitones).rem_euclid(12) as usize;
+    Some(format!("{}{}", TONICS[tonic], mode))
+}
+
+/// The LilyPond style note value (4 for a quarter note) of a length in whole notes.
+fn note_value(length: f32) -> u32 {
+    let mut value = 1;
+    while (1.0 / value as f32) > length + f32::EPSILON && value < 128 {
+        value *= 2;
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn song(keys: &[u8]) -> SongFile {
+        let notes = keys
+            .iter()
+            .enumerate()
+            .map(|(i, &midi_key)| SongNote {
+                timestamp: i as f32 * 0.5,
+                midi_key,
+                duration: 4,
+                duration_length: 0.5,
+                notehead_id: format!("note-{}", i),
+            })
+            .collect();
+        let info = SongInfo {
+            key: Some("D".to_string()),
+            ..Default::default()
+        };
+        SongFile::from_notes("test".to_string(), info, notes)
+    }
+
+    #[test]
+    fn notes_file() {
+        let text = "0.0\tnote\t62\t8\t0.125\tNote-1\n0.125\tnote\t66\t4\t0.25\tNote-2\n";
+        let song = SongFile::from_str(text, "test.notes".to_string()).unwrap();
+        let keys: Vec<u8> = song.notes.iter().map(|note| note.midi_key).collect();
+        assert_eq!(keys, [62, 66]);
+        assert_eq!(song.notes[1].notehead_id, "Note-2");
+        assert_eq!(song.events.len(), 4);
+        // a whole note a second, without a tempo to click the beat of
+        assert_eq!(song.info.tempo, None);
+        assert_eq!(song.notes[1].timestamp, 0.125);
+        assert!(song.beats().is_empty());
+    }
+
+    #[test]
+    fn notes_file_takes_the_details_of_the_abc_file() {
+        let dir = std::env::temp_dir().join(format!("whstlrs-{}-details", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let notes: String = [71, 74, 74, 71, 67, 69, 67, 66, 67]
+            .iter()
+            .enumerate()
+            .map(|(i, key)| {
+                format!(
+                    "{}\tnote\t{}\t8\t0.125\tNote-{}\n",
+                    i as f32 * 0.125,
+                    key,
+                    i
+                )
+            })
+            .collect();
+        fs::write(dir.join("tune.notes"), notes).unwrap();
+        let abc = "X:1\nT:Jig\nM:6/8\nL:1/8\nQ:3/8=80\nK:G\nBd|dBG AGF|G\n";
+        fs::write(dir.join("tune.abc"), abc).unwrap();
+        let song = SongFile::new(dir.join("tune.notes"));
+        fs::remove_file(dir.join("tune.abc")).unwrap();
+        let alone = SongFile::new(dir.join("tune.notes"));
+        fs::remove_dir_all(&dir).unwrap();
+
+        let song = song.unwrap();
+        assert_eq!(song.info.title.as_deref(), Some("Jig"));
+        assert_eq!(
+            song.info.meter.map(|m| (m.numerator, m.denominator)),
+            Some((6, 8))
+        );
+        assert_eq!(song.info.tempo, Some(120.0));
+        // an eighth note is a quarter of a second at 120 bpm
+        assert_eq!(song.notes[2].timestamp, 0.5);
+        assert_eq!(song.notes[2].duration_length, 0.25);
+        // the bars of the abc file, after the pickup
+        assert_eq!(song.bars.get(1), Some(&Duration::from_millis(500)));
+        assert_eq!(song.beats().first().map(|beat| beat.1), Some(false));
+
+        let alone = alone.unwrap();
+        assert_eq!(alone.info.tempo, None);
+        assert_eq!(alone.notes[2].timestamp, 0.25);
+    }
+
+    #[test]
+    fn malformed_notes_file_is_an_error() {
+        let read = |text: &str| SongFile::from_str(text, "test.notes".to_string());
+        assert!(read("0.0\tnote\t62\n").is_err());
+        assert!(read("0.0\tnote\tD\t8\t0.125\tNote-1\n").is_err());
+        assert!(read("0.0\tnote\t200\t8\t0.125\tNote-1\n").is_err());
+        assert!(read("x\tnote\t62\t8\t0.125\tNote-1\n").is_err());
+        // lines of other kinds and single fields are left out
+        assert!(read("0.0\tbar\n\nend\n").is_ok_and(|song| song.notes.is_empty()));
+    }
+
+    #[test]
+    fn key_names_wrap_around() {
+        assert_eq!(transpose_key_name("B", 1).as_deref(), Some("C"));
+        assert_eq!(transpose_key_name("C", -1).as_deref(), Some("B"));
+        assert_eq!(transpose_key_name("F#m", 2).as_deref(), Some("Abm"));
+        assert_eq!(transpose_key_name("Ador", 3).as_deref(), Some("Cdor"));
+        assert_eq!(transpose_key_name("Bb", -13).as_deref(), Some("A"));
+        assert_eq!(transpose_key_name("G", 24).as_deref(), Some("G"));
+        assert_eq!(transpose_key_name("none", 5).as_deref(), Some("none"));
+    }
+
+    #[test]
+    fn transpose_moves_notes_events_and_key() {
+        let mut song = song(&[62, 66, 69]);
+        song.transpose(-2, 1).unwrap();
+        let keys: Vec<u8> = song.notes.iter().map(|note| note.midi_key).collect();
+        assert_eq!(keys, [72, 76, 79]);
+        assert!(song.events.iter().all(|event| event.midi_key >= 72));
+        assert_eq!(song.info.key.as_deref(), Some("C"));
+    }
+
+    #[test]
+    fn transpose_out_of_range_changes_nothing() {
+        let mut song = song(&[62, 120]);
+        assert!(song.transpose(0, 1).is_err());
+        let keys: Vec<u8> = song.notes.iter().map(|note| note.midi_key).collect();
+        assert_eq!(keys, [62, 120]);
+        assert_eq!(song.info.key.as_deref(), Some("D"));
+    }
+
+    fn playback(keys: &[u8]) -> PlaybackState {
+        PlaybackState::new(Arc::new(Song::new(song(keys))))
+    }
+
+    fn millis(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    fn keys_on(events: &[&SongEvent]) -> Vec<u8> {
+        events
+            .iter()
+            .filter(|event| matches!(event.message, MidiMessage::NoteOn { .. }))
+            .map(|event| event.midi_key)
+            .collect()
+    }
+
+    #[test]
+    fn seek_plays_on_from_the_new_position() {
+        let mut playback = playback(&[62, 64, 66, 67]);
+        playback.seek(millis(750));
+        assert_eq!(playback.time(), millis(750));
+        assert!(playback.update(Duration::ZERO).is_empty());
+        assert_eq!(keys_on(&playback.update(millis(250))), [66]);
+
+        // the notes from the new position are played again
+        playback.seek(millis(500));
+        assert_eq!(keys_on(&playback.update(Duration::ZERO)), [64]);
+        playback.restart();
+        assert_eq!(playback.time(), Duration::ZERO);
+        assert_eq!(keys_on(&playback.update(Duration::ZERO)), [62]);
+    }
+
+    #[test]
+    fn seek_past_the_end_finishes() {
+        let mut playback = playback(&[62, 64]);
+        playback.seek(Duration::from_secs(100));
+        assert!(playback.is_finished());
+        assert!(playback.update(millis(100)).is_empty());
+        playback.restart();
+        assert!(!playback.is_finished());
+    }
+
+    #[test]
+    fn seek_to_bar() {
+        // bars of a second, a whole note at the pace of a song without a tempo
+        let mut playback = playback(&[62, 64, 66, 67, 69, 71]);
+        assert_eq!(playback.song().file.bars.len(), 3);
+        playback.seek_to_bar(1);
+        assert_eq!(playback.time(), Duration::from_secs(1));
+        assert_eq!(playback.bar(), 1);
+        playback.seek_to_bar(0);
+        assert_eq!(playback.time(), Duration::ZERO);
+        playback.seek_to_bar(99);
+        assert_eq!(playback.time(), Duration::from_secs(2));
+    }
+
+    #[test]
+    fn speed_is_clamped() {
+        let mut playback = playback(&[62]);
+        playback.set_speed(10.0);
+        assert_eq!(playback.speed(), MAX_SPEED);
+        playback.set_speed(0.0);
+        assert_eq!(playback.speed(), MIN_SPEED);
+        playback.update(Duration::from_secs(2));
+        assert_eq!(playback.time(), millis(500));
+
+        // a song without a tempo keeps its speed
+        assert_eq!(playback.bpm(), None);
+        assert!(!playback.set_bpm(60.0));
+        assert_eq!(playback.speed(), MIN_SPEED);
+
+        let mut file = song(&[62]);
+        file.info.tempo = Some(120.0);
+        let mut playback = PlaybackState::new(Arc::new(Song::new(file)));
+        assert!(playback.set_bpm(60.0));
+        assert_eq!(playback.speed(), 0.5);
+        assert_eq!(playback.bpm(), Some(60.0));
+        assert!(playback.set_bpm(1000.0));
+        assert_eq!(playback.speed(), MAX_SPEED);
+    }
+
+    #[test]
+    fn loop_region_repeats() {
+        let mut playback = playback(&[62, 64, 66, 67]);
+        // an empty region is no loop
+        playback.set_loop(Duration::from_secs(1), millis(500));
+        assert!(playback.loop_region().is_none());
+
+        playback.set_loop(millis(500), Duration::from_secs(1));
+        assert_eq!(playback.time(), millis(500));
+        let region = playback.loop_region_mut().unwrap();
+        region.repeats = Some(2);
+        // the end of the region isn't played
+        assert_eq!(keys_on(&playback.update(millis(600))), [64]);
+        assert!(playback.loop_ended());
+
+        playback.next_repetition(false);
+        assert_eq!(playback.time(), millis(500));
+        assert_eq!(playback.loop_region().unwrap().count, 1);
+        playback.update(millis(500));
+        playback.next_repetition(false);
+        // played twice, the song goes on after the region
+        assert!(playback.loop_region().is_none());
+        assert_eq!(playback.time(), Duration::from_secs(1));
+        assert_eq!(keys_on(&playback.update(Duration::ZERO)), [66]);
+    }
+
+    #[test]
+    fn loop_region_speeds_up_to_the_target() {
+        let mut playback = playback(&[62, 64, 66, 67]);
+        playback.set_speed(0.8);
+        playback.set_loop(Duration::ZERO, Duration::from_secs(1));
+        let region = playback.loop_region_mut().unwrap();
+        region.speed_step = 0.15;
+        region.target_speed = 1.0;
+
+        playback.next_repetition(true);
+        assert!((playback.speed() - 0.95).abs() < 1e-6);
+        // a repetition with mistakes keeps the speed
+        playback.next_repetition(false);
+        assert!((playback.speed() - 0.95).abs() < 1e-6);
+        playback.next_repetition(true);
+        assert_eq!(playback.speed(), 1.0);
+        playback.next_repetition(true);
+        assert_eq!(playback.speed(), 1.0);
+        assert_eq!(playback.loop_region().unwrap().count, 4);
+    }
+
+    #[test]
+    fn wait_mode_halts_at_each_note() {
+        let mut playback = playback(&[62, 64, 66]);
+        playback.set_wait_mode(true, true);
+        assert!(playback.update(Duration::from_secs(1)).is_empty());
+        assert!(playback.is_waiting());
+        assert_eq!(playback.time(), Duration::ZERO);
+        assert_eq!(playback.waiting_for().map(|note| note.midi_key), Some(62));
+
+        assert!(!playback.user_note(64));
+        // any octave of the note will do
+        assert!(playback.user_note(74));
+        assert_eq!(keys_on(&playback.update(Duration::from_secs(1))), [62]);
+        // halted at the next note, the one before it ends
+        assert_eq!(playback.time(), millis(500));
+        assert!(playback.is_waiting());
+        assert_eq!(playback.waiting_for().map(|note| note.midi_key), Some(64));
+
+        // a seek waits for the note at the new position
+        playback.seek(Duration::from_secs(1));
+        assert_eq!(playback.waiting_for().map(|note| note.midi_key), Some(66));
+
+        playback.set_wait_mode(false, true);
+        assert!(!playback.is_waiting());
+        assert_eq!(keys_on(&playback.update(Duration::ZERO)), [66]);
+    }
+
+    #[test]
+    fn wait_mode_with_octave_equivalence_off() {
+        let mut playback = playback(&[62]);
+        playback.set_wait_mode(true, false);
+        assert!(!playback.user_note(74));
+        assert!(playback.user_note(62));
+        assert!(!playback.is_waiting());
+    }
+}
@@ -0,0 +1,391 @@
+//! Scores what the player plays against the notes of the song.
+
+use std::fmt;
+use std::time::Duration;
+
+use super::SongFile;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoteResult {
+    Hit,
+    Early,
+    Late,
+    Wrong,
+    Missed,
+}
+
+/// How far from the note a played note may be, in real time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimingWindows {
+    /// Played within this counts as a hit.
+    pub hit: Duration,
+    /// Played within this counts as early or late, further away isn't matched.
+    pub ok: Duration,
+}
+
+impl Default for TimingWindows {
+    fn default() -> Self {
+        Self {
+            hit: Duration::from_millis(80),
+            ok: Duration::from_millis(200),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct ScoredNote {
+    timestamp: Duration,
+    midi_key: u8,
+    notehead_id: String,
+    result: Option<NoteResult>,
+    /// Seconds the note was played after (positive) or before it was due, in real time.
+    deviation: Option<f32>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ScoreSummary {
+    pub hit: usize,
+    pub early: usize,
+    pub late: usize,
+    pub wrong: usize,
+    pub missed: usize,
+    /// Played notes that don't belong to any note of the song.
+    pub extra: usize,
+    /// Share of the notes played with the right pitch in time, 0.0 to 1.0.
+    pub accuracy: f32,
+    /// Mean timing deviation in seconds, negative when playing ahead.
+    pub mean_deviation: f32,
+    pub mean_abs_deviation: f32,
+    pub deviation_std_dev: f32,
+}
+
+pub struct Scorer {
+    windows: TimingWindows,
+    octave_equivalence: bool,
+    notes: Vec<ScoredNote>,
+    extra: usize,
+    /// Where the player started playing, earlier notes without a result aren't missed.
+    from: Duration,
+}
+
+impl Scorer {
+    pub fn new(song: &SongFile, windows: TimingWindows, octave_equivalence: bool) -> Self {
+        let notes = song
+            .playing_order()
+            .into_iter()
+            .map(|i| {
+                let note = &song.notes[i];
+                ScoredNote {
+                    timestamp: Duration::from_secs_f32(note.timestamp),
+                    midi_key: note.midi_key,
+                    notehead_id: note.notehead_id.clone(),
+                    result: None,
+                    deviation: None,
+                }
+            })
+            .collect();
+        Self {
+            windows,
+            octave_equivalence,
+            notes,
+            extra: 0,
+            from: Duration::ZERO,
+        }
+    }
+
+    pub fn windows(&self) -> TimingWindows {
+        self.windows
+    }
+
+    fn same_pitch(&self, a: u8, b: u8) -> bool {
+        a == b || (self.octave_equivalence && a % 12 == b % 12)
+    }
+
+    /// Scores a note played at song position `time`, with the playback at
+    /// `speed`. Returns the notehead and result of the matched song note.
+    pub fn user_note(
+        &mut self,
+        time: Duration,
+        midi_key: u8,
+        speed: f32,
+    ) -> Option<(&str, NoteResult)> {
+        let speed = speed.max(f32::EPSILON);
+        // windows are in real time, song time runs at the playback speed
+        let ok = self.windows.ok.mul_f32(speed);
+        let hit = self.windows.hit.as_secs_f32();
+        let first = self.notes.partition_point(|n| n.timestamp + ok < time);
+
+        let mut right: Option<(usize, Duration)> = None;
+        let mut nearest: Option<(usize, Duration)> = None;
+        for (i, note) in self.notes[first..].iter().enumerate() {
+            if note.timestamp > time + ok {
+                break;
+            }
+            if !matches!(note.result, None | Some(NoteResult::Wrong)) {
+                continue;
+            }
+            let distance = note.timestamp.abs_diff(time);
+            if self.same_pitch(note.midi_key, midi_key) && right.is_none_or(|(_, d)| distance < d) {
+                right = Some((first + i, distance));
+            }
+            if note.result.is_none() && nearest.is_none_or(|(_, d)| distance < d) {
+                nearest = Some((first + i, distance));
+            }
+        }
+
+        let (index, result) = match (right, nearest) {
+            (Some((index, _)), _) => {
+                let note = &mut self.notes[index];
+                let deviation = (time.as_secs_f32() - note.timestamp.as_secs_f32()) / speed;
+                note.deviation = Some(deviation);
+                let result = if deviation.abs() <= hit {
+                    NoteResult::Hit
+                } else if deviation < 0.0 {
+                    NoteResult::Early
+                } else {
+                    NoteResult::Late
+                };
+                (index, result)
+            }
+            (None, Some((index, _))) => (index, NoteResult::Wrong),
+            (None, None) => {
+                self.extra += 1;
+                return None;
+            }
+        };
+        let note = &mut self.notes[index];
+        note.result = Some(result);
+        Some((&note.notehead_id, result))
+    }
+
+    /// Marks the notes that weren't played in time as missed, returns their noteheads.
+    pub fn update(&mut self, time: Duration, speed: f32) -> Vec<&str> {
+        let ok = self.windows.ok.mul_f32(speed.max(f32::EPSILON));
+        self.notes
+            .iter_mut()
+            .take_while(|note| note.timestamp + ok < time)
+            .filter(|note| note.timestamp >= self.from && note.result.is_none())
+            .map(|note| {
+                note.result = Some(NoteResult::Missed);
+                note.notehead_id.as_str()
+            })
+            .collect()
+    }
+
+    /// Forgets the results from `time` on, to play that part again. Returns
+    /// the noteheads that lost their result.
+    pub fn reset_from(&mut self, time: Duration) -> Vec<&str> {
+        self.from = time;
+        let first = self.notes.partition_point(|n| n.timestamp < time);
+        self.notes[first..]
+            .iter_mut()
+            .filter(|note| note.result.is_some())
+            .map(|note| {
+                note.result = None;
+                note.deviation = None;
+                note.notehead_id.as_str()
+            })
+            .collect()
+    }
+
+    /// All notes between `start` and `end` are played with the right pitch in time.
+    pub fn is_clean(&self, start: Duration, end: Duration) -> bool {
+        self.notes
+            .iter()
+            .filter(|note| note.timestamp >= start && note.timestamp < end)
+            .all(|note| {
+                matches!(
+                    note.result,
+                    Some(NoteResult::Hit | NoteResult::Early | NoteResult::Late)
+                )
+            })
+    }
+
+    /// Result of each note, with the moment it is due.
+    pub fn results(&self) -> impl Iterator<Item = (Duration, Option<NoteResult>)> + '_ {
+        self.notes.iter().map(|note| (note.timestamp, note.result))
+    }
+
+    pub fn summary(&self) -> ScoreSummary {
+        let mut summary = ScoreSummary {
+            extra: self.extra,
+            ..Default::default()
+        };
+        for note in self.notes.iter() {
+            match note.result {
+                Some(NoteResult::Hit) => summary.hit += 1,
+                Some(NoteResult::Early) => summary.early += 1,
+                Some(NoteResult::Late) => summary.late += 1,
+                Some(NoteResult::Wrong) => summary.wrong += 1,
+                Some(NoteResult::Missed) => summary.missed += 1,
+                None => (),
+            }
+        }
+        let played = summary.hit + summary.early + summary.late;
+        let scored = played + summary.wrong + summary.missed;
+        if scored > 0 {
+            summary.accuracy = played as f32 / scored as f32;
+        }
+
+        let deviations: Vec<f32> = self.notes.iter().filter_map(|n| n.deviation).collect();
+        if !deviations.is_empty() {
+            let n = deviations.len() as f32;
+            summary.mean_deviation = deviations.iter().sum::<f32>() / n;
+            summary.mean_abs_deviation = deviations.iter().map(|d| d.abs()).sum::<f32>() / n;
+            summary.deviation_std_dev = (deviations
+                .iter()
+                .map(|d| (d - summary.mean_deviation).powi(2))
+                .sum::<f32>()
+                / n)
+                .sqrt();
+        }
+        summary
+    }
+}
+
+impl fmt::Display for ScoreSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:.0}% accuracy: {} hit, {} early, {} late, {} wrong, {} missed, {} extra; \
+             timing {:+.0}ms on average (mean deviation {:.0}ms, std dev {:.0}ms)",
+            self.accuracy * 100.0,
+            self.hit,
+            self.early,
+            self.late,
+            self.wrong,
+            self.missed,
+            self.extra,
+            self.mean_deviation * 1000.0,
+            self.mean_abs_deviation * 1000.0,
+            self.deviation_std_dev * 1000.0
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::song::{SongInfo, SongNote};
+
+    /// Notes a second apart, from the start of the song.
+    fn scorer_for(keys: &[u8], octave_equivalence: bool) -> Scorer {
+        let notes = keys
+            .iter()
+            .enumerate()
+            .map(|(i, &midi_key)| SongNote {
+                timestamp: i as f32,
+                midi_key,
+                duration: 4,
+                duration_length: 0.5,
+                notehead_id: format!("note-{}", i),
+            })
+            .collect();
+        let song = SongFile::from_notes("test".to_string(), SongInfo::default(), notes);
+        Scorer::new(&song, TimingWindows::default(), octave_equivalence)
+    }
+
+    fn played(scorer: &mut Scorer, millis: u64, midi_key: u8, speed: f32) -> Option<NoteResult> {
+        scorer
+            .user_note(Duration::from_millis(millis), midi_key, speed)
+            .map(|(_, result)| result)
+    }
+
+    #[test]
+    fn timing_of_the_right_note() {
+        let mut scorer = scorer_for(&[62, 64, 66, 67, 69], true);
+        assert_eq!(played(&mut scorer, 0, 62, 1.0), Some(NoteResult::Hit));
+        assert_eq!(played(&mut scorer, 900, 64, 1.0), Some(NoteResult::Early));
+        assert_eq!(played(&mut scorer, 2150, 66, 1.0), Some(NoteResult::Late));
+        // the windows are 80 and 200 ms
+        assert_eq!(played(&mut scorer, 3079, 67, 1.0), Some(NoteResult::Hit));
+        assert_eq!(played(&mut scorer, 4081, 69, 1.0), Some(NoteResult::Late));
+    }
+
+    #[test]
+    fn window_boundaries() {
+        let mut scorer = scorer_for(&[62, 64], true);
+        assert_eq!(played(&mut scorer, 801, 64, 1.0), Some(NoteResult::Early));
+        let mut scorer = scorer_for(&[62, 64], true);
+        assert_eq!(played(&mut scorer, 1199, 64, 1.0), Some(NoteResult::Late));
+        // outside of the ok window the note isn't matched
+        let mut scorer = scorer_for(&[62, 64], true);
+        assert_eq!(played(&mut scorer, 1201, 64, 1.0), None);
+        assert_eq!(played(&mut scorer, 500, 64, 1.0), None);
+        assert_eq!(scorer.summary().extra, 2);
+    }
+
+    #[test]
+    fn wrong_notes_can_be_corrected() {
+        let mut scorer = scorer_for(&[62, 64], true);
+        assert_eq!(played(&mut scorer, 1000, 65, 1.0), Some(NoteResult::Wrong));
+        assert_eq!(played(&mut scorer, 1050, 64, 1.0), Some(NoteResult::Hit));
+        // a note is scored once
+        assert_eq!(played(&mut scorer, 1060, 64, 1.0), None);
+    }
+
+    #[test]
+    fn octave_equivalence() {
+        let mut scorer = scorer_for(&[64], true);
+        assert_eq!(played(&mut scorer, 0, 76, 1.0), Some(NoteResult::Hit));
+        let mut scorer = scorer_for(&[64], false);
+        assert_eq!(played(&mut scorer, 0, 76, 1.0), Some(NoteResult::Wrong));
+    }
+
+    #[test]
+    fn notes_not_played_in_time_are_missed() {
+        let mut scorer = scorer_for(&[62, 64, 66], true);
+        assert_eq!(played(&mut scorer, 0, 62, 1.0), Some(NoteResult::Hit));
+        assert!(scorer.update(Duration::from_millis(1150), 1.0).is_empty());
+        assert_eq!(scorer.update(Duration::from_millis(1250), 1.0), ["note-1"]);
+        assert!(scorer.update(Duration::from_millis(1500), 1.0).is_empty());
+
+        // notes before the start of the part played again aren't missed
+        let mut scorer = scorer_for(&[62, 64, 66], true);
+        assert!(scorer.reset_from(Duration::from_secs(1)).is_empty());
+        assert_eq!(
+            scorer.update(Duration::from_secs(3), 1.0),
+            ["note-1", "note-2"]
+        );
+    }
+
+    #[test]
+    fn windows_are_in_real_time() {
+        // at half speed the song moves 40 ms for an 80 ms window
+        let mut scorer = scorer_for(&[62, 64, 66], true);
+        assert_eq!(played(&mut scorer, 1030, 64, 0.5), Some(NoteResult::Hit));
+        assert_eq!(played(&mut scorer, 2050, 66, 0.5), Some(NoteResult::Late));
+        assert_eq!(played(&mut scorer, 110, 62, 0.5), None);
+        let deviation = scorer.summary().mean_deviation;
+        assert!((deviation - 0.08).abs() < 1e-4);
+
+        // at double speed it moves 160 ms
+        let mut scorer = scorer_for(&[62, 64], true);
+        assert_eq!(played(&mut scorer, 1150, 64, 2.0), Some(NoteResult::Hit));
+        assert_eq!(played(&mut scorer, 350, 62, 2.0), Some(NoteResult::Late));
+        assert!(scorer.update(Duration::from_millis(1350), 2.0).is_empty());
+    }
+
+    #[test]
+    fn summary_and_clean_regions() {
+        let mut scorer = scorer_for(&[62, 64, 66, 67], true);
+        played(&mut scorer, 0, 62, 1.0);
+        played(&mut scorer, 1100, 64, 1.0);
+        played(&mut scorer, 2000, 61, 1.0);
+        scorer.update(Duration::from_secs(4), 1.0);
+        let summary = scorer.summary();
+        assert_eq!(
+            (summary.hit, summary.late, summary.wrong, summary.missed),
+            (1, 1, 1, 1)
+        );
+        assert_eq!(summary.accuracy, 0.5);
+        assert!((summary.mean_deviation - 0.05).abs() < 1e-4);
+        assert!(scorer.is_clean(Duration::ZERO, Duration::from_secs(2)));
+        assert!(!scorer.is_clean(Duration::ZERO, Duration::from_secs(3)));
+
+        // playing the part again forgets its results
+        assert_eq!(scorer.reset_from(Duration::from_secs(1)).len(), 3);
+        assert_eq!(scorer.summary().hit, 1);
+        assert_eq!(scorer.summary().accuracy, 1.0);
+    }
+}
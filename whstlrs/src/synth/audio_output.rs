@@ -0,0 +1,75 @@
+use std::sync::{Arc, Mutex};
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{FromSample, SampleFormat, SizedSample, Stream, StreamConfig};
+use midly::MidiMessage;
+
+use super::Synth;
+
+/// Plays the synthesizer on the default audio device.
+pub struct AudioOutput {
+    synth: Arc<Mutex<Synth>>,
+    _stream: Stream,
+}
+
+impl AudioOutput {
+    pub fn new() -> Result<Self, String> {
+        let device = cpal::default_host()
+            .default_output_device()
+            .ok_or(String::from("No audio output device found"))?;
+        let supported = device
+            .default_output_config()
+            .map_err(|e| format!("Could not open audio output: {}", e))?;
+        let config = supported.config();
+        let synth = Arc::new(Mutex::new(Synth::new(config.sample_rate.0)));
+
+        let stream = match supported.sample_format() {
+            SampleFormat::F32 => Self::stream::<f32>(&device, &config, synth.clone()),
+            SampleFormat::I16 => Self::stream::<i16>(&device, &config, synth.clone()),
+            SampleFormat::U16 => Self::stream::<u16>(&device, &config, synth.clone()),
+            format => Err(format!("Audio sample format {} is not supported", format)),
+        }?;
+        stream
+            .play()
+            .map_err(|e| format!("Could not start audio output: {}", e))?;
+        if let Ok(name) = device.name() {
+            log::info!("Playing on audio output {}", name);
+        }
+        Ok(Self {
+            synth,
+            _stream: stream,
+        })
+    }
+
+    fn stream<T: SizedSample + FromSample<f32>>(
+        device: &cpal::Device,
+        config: &StreamConfig,
+        synth: Arc<Mutex<Synth>>,
+    ) -> Result<Stream, String> {
+        let channels = config.channels as usize;
+        let mut mono = Vec::new();
+        device
+            .build_output_stream(
+                config,
+                move |data: &mut [T], _| {
+                    mono.resize(data.len() / channels, 0.0);
+                    match synth.lock() {
+                        Ok(mut synth) => synth.render(&mut mono),
+                        Err(_) => mono.fill(0.0),
+                    }
+                    for (frame, &sample) in data.chunks_mut(channels).zip(mono.iter()) {
+                        frame.fill(T::from_sample(sample));
+                    }
+                },
+                |e| log::error!("Audio output: {}", e),
+                None,
+            )
+            .map_err(|e| format!("Could not open audio output: {}", e))
+    }
+
+    pub fn midi_event(&self, channel: u8, message: &MidiMessage) {
+        if let Ok(mut synth) = self.synth.lock() {
+            synth.midi_event(channel, message);
+        }
+    }
+}
@@ -0,0 +1,224 @@
+//! A small synthesizer with a whistle like voice, to hear the song without a
+//! MIDI synthesizer.
+
+mod audio_output;
+mod wav;
+
+use std::f32::consts::TAU;
+use std::time::Duration;
+
+use midly::MidiMessage;
+
+use crate::output_manager::{CLICK_ACCENT, DRUM_CHANNEL};
+
+pub use audio_output::AudioOutput;
+pub use wav::write_wav;
+
+pub const SAMPLE_RATE: u32 = 44_100;
+/// Loudness of the partials, a whistle is close to a sine.
+const HARMONICS: [f32; 4] = [1.0, 0.22, 0.06, 0.02];
+/// Envelope times in seconds and the sustain level.
+const ATTACK: f32 = 0.03;
+const DECAY: f32 = 0.08;
+const SUSTAIN: f32 = 0.8;
+const RELEASE: f32 = 0.06;
+/// Breath noise while the note sounds, and the extra chiff at its start.
+const BREATH: f32 = 0.04;
+const CHIFF: f32 = 0.15;
+const CHIFF_TIME: f32 = 0.04;
+const VIBRATO_RATE: f32 = 5.5;
+/// Vibrato depth as a fraction of the frequency, it fades in after the attack.
+const VIBRATO_DEPTH: f32 = 0.004;
+const VIBRATO_DELAY: f32 = 0.3;
+const GAIN: f32 = 0.3;
+/// Decay time of the clicks on the percussion channel.
+const CLICK_DECAY: f32 = 0.012;
+
+struct Voice {
+    channel: u8,
+    key: u8,
+    frequency: f32,
+    phase: f32,
+    vibrato_phase: f32,
+    velocity: f32,
+    /// Seconds since the note started.
+    time: f32,
+    level: f32,
+    /// Level at the NoteOff, the release fades from there.
+    released: Option<f32>,
+    /// A click on the percussion channel, it just decays.
+    click: bool,
+}
+
+impl Voice {
+    fn envelope(&mut self, dt: f32) -> f32 {
+        self.time += dt;
+        if self.click {
+            self.level = (-self.time / CLICK_DECAY).exp();
+            return self.level;
+        }
+        self.level = match self.released {
+            Some(from) => (self.level - from * dt / RELEASE).max(0.0),
+            None if self.time < ATTACK => self.time / ATTACK,
+            None if self.time < ATTACK + DECAY => {
+                1.0 - (1.0 - SUSTAIN) * (self.time - ATTACK) / DECAY
+            }
+            None => SUSTAIN,
+        };
+        self.level
+    }
+
+    fn is_silent(&self) -> bool {
+        if self.click {
+            return self.time > CLICK_DECAY * 10.0;
+        }
+        self.released.is_some() && self.level <= 0.0
+    }
+}
+
+pub struct Synth {
+    sample_rate: f32,
+    voices: Vec<Voice>,
+    /// State of the noise generator and its low-pass filter.
+    noise: u32,
+    breath: f32,
+}
+
+impl Synth {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate: sample_rate as f32,
+            voices: Vec::new(),
+            noise: 0x1234_5678,
+            breath: 0.0,
+        }
+    }
+
+    pub fn midi_event(&mut self, channel: u8, message: &MidiMessage) {
+        match *message {
+            MidiMessage::NoteOn { key, vel } if vel > 0 && channel == DRUM_CHANNEL => {
+                // the accent clicks higher
+                let frequency = if key.as_int() == CLICK_ACCENT {
+                    1600.0
+                } else {
+                    1100.0
+                };
+                self.voices.push(Voice {
+                    channel,
+                    key: key.as_int(),
+                    frequency,
+                    phase: 0.0,
+                    vibrato_phase: 0.0,
+                    velocity: vel.as_int() as f32 / 127.0,
+                    time: 0.0,
+                    level: 1.0,
+                    released: None,
+                    click: true,
+                });
+            }
+            MidiMessage::NoteOn { key, vel } if vel > 0 => {
+                let key = key.as_int();
+                self.release(|v| v.channel == channel && v.key == key);
+                self.voices.push(Voice {
+                    channel,
+                    key,
+                    frequency: 440.0 * 2f32.powf((key as f32 - 69.0) / 12.0),
+                    phase: 0.0,
+                    vibrato_phase: 0.0,
+                    velocity: vel.as_int() as f32 / 127.0,
+                    time: 0.0,
+                    level: 0.0,
+                    released: None,
+                    click: false,
+                });
+            }
+            MidiMessage::NoteOn { key, .. } | MidiMessage::NoteOff { key, .. } => {
+                let key = key.as_int();
+                self.release(|v| v.channel == channel && v.key == key);
+            }
+            // all sound off, all notes off
+            MidiMessage::Controller { controller, .. }
+                if controller == 120 || controller == 123 =>
+            {
+                self.release(|v| v.channel == channel);
+            }
+            _ => (),
+        }
+    }
+
+    fn release(&mut self, matches: impl Fn(&Voice) -> bool) {
+        for voice in self.voices.iter_mut().filter(|v| v.released.is_none()) {
+            if matches(voice) {
+                voice.released = Some(voice.level);
+            }
+        }
+    }
+
+    fn next_noise(&mut self) -> f32 {
+        // xorshift
+        self.noise ^= self.noise << 13;
+        self.noise ^= self.noise >> 17;
+        self.noise ^= self.noise << 5;
+        let white = self.noise as f32 / u32::MAX as f32 * 2.0 - 1.0;
+        self.breath += (white - self.breath) * 0.3;
+        self.breath
+    }
+
+    /// Fills `out` with the next mono samples.
+    pub fn render(&mut self, out: &mut [f32]) {
+        let dt = 1.0 / self.sample_rate;
+        for sample in out.iter_mut() {
+            let noise = self.next_noise();
+            let mut value = 0.0;
+            for voice in self.voices.iter_mut() {
+                let level = voice.envelope(dt) * voice.velocity;
+                if voice.click {
+                    voice.phase = (voice.phase + voice.frequency * dt).fract();
+                    value += level * (TAU * voice.phase).sin();
+                    continue;
+                }
+                let vibrato = VIBRATO_DEPTH
+                    * ((voice.time - VIBRATO_DELAY) / VIBRATO_DELAY).clamp(0.0, 1.0)
+                    * (TAU * voice.vibrato_phase).sin();
+                voice.vibrato_phase = (voice.vibrato_phase + VIBRATO_RATE * dt).fract();
+                voice.phase = (voice.phase + voice.frequency * (1.0 + vibrato) * dt).fract();
+
+                let tone: f32 = HARMONICS
+                    .iter()
+                    .enumerate()
+                    .map(|(i, amplitude)| amplitude * (TAU * voice.phase * (i + 1) as f32).sin())
+                    .sum();
+                let chiff = CHIFF * (1.0 - voice.time / CHIFF_TIME).max(0.0);
+                value += level * (tone + noise * (BREATH + chiff));
+            }
+            *sample = (value * GAIN).clamp(-1.0, 1.0);
+        }
+        self.voices.retain(|voice| !voice.is_silent());
+    }
+
+    /// Renders timed MIDI messages, sorted by time, until `end` and the
+    /// release of the last notes.
+    pub fn render_offline<'a>(
+        &mut self,
+        events: impl IntoIterator<Item = (Duration, u8, &'a MidiMessage)>,
+        end: Duration,
+    ) -> Vec<f32> {
+        let mut samples = Vec::new();
+        let mut rendered = 0;
+        for (time, channel, message) in events {
+            let index = (time.as_secs_f32() * self.sample_rate) as usize;
+            if index > rendered {
+                samples.resize(index, 0.0);
+                self.render(&mut samples[rendered..]);
+                rendered = index;
+            }
+            self.midi_event(channel, message);
+        }
+        let end = ((end.as_secs_f32() + RELEASE * 2.0) * self.sample_rate) as usize;
+        if end > rendered {
+            samples.resize(end, 0.0);
+            self.render(&mut samples[rendered..]);
+        }
+        samples
+    }
+}
@@ -1 +1,2 @@
+pub mod dirs;
 pub mod window;
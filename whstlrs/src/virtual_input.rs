@@ -0,0 +1,175 @@
+use midly::{num::u7, MidiMessage};
+use winit::event::{ElementState, KeyEvent, WindowEvent};
+use winit::event_loop::EventLoopProxy;
+use winit::keyboard::{KeyCode, PhysicalKey};
+
+use crate::instrument::Instrument;
+use crate::whistle::WhistleKey;
+use crate::WhstlrsEvent;
+
+/// Keys for the finger holes, from the top hole down: 1 2 3 for the left hand
+/// and 7 8 9 for the right. They, and the breath key, are apart from the keys
+/// of the scenes.
+const HOLE_KEYS: [KeyCode; 6] = [
+    KeyCode::Digit1,
+    KeyCode::Digit2,
+    KeyCode::Digit3,
+    KeyCode::Digit7,
+    KeyCode::Digit8,
+    KeyCode::Digit9,
+];
+const BREATH_KEY: KeyCode = KeyCode::KeyB;
+const TOGGLE_KEY: KeyCode = KeyCode::F2;
+const CHANNEL: u8 = 0;
+const VELOCITY: u8 = 100;
+
+/// Plays the computer keyboard like a whistle: six keys close the finger
+/// holes, shift overblows and B blows. The notes go the same way as those
+/// of a MIDI device.
+pub struct VirtualInput {
+    tx: EventLoopProxy<WhstlrsEvent>,
+    enabled: bool,
+    instrument: Instrument,
+    whistle_key: WhistleKey,
+    /// Closed holes, the top hole highest.
+    holes: u16,
+    overblow: bool,
+    breath: bool,
+    sounding: Option<u8>,
+}
+
+impl VirtualInput {
+    pub fn new(tx: EventLoopProxy<WhstlrsEvent>) -> Self {
+        Self {
+            tx,
+            enabled: false,
+            instrument: Instrument::default(),
+            whistle_key: WhistleKey::default(),
+            holes: 0,
+            overblow: false,
+            breath: false,
+            sounding: None,
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        if enabled && !self.enabled {
+            log::info!(
+                "Keyboard whistle on: 1 2 3 7 8 9 close the holes, shift overblows, B blows, F2 turns it off"
+            );
+        } else if !enabled && self.enabled {
+            log::info!("Keyboard whistle off");
+        }
+        self.enabled = enabled;
+        self.holes = 0;
+        self.overblow = false;
+        self.breath = false;
+        self.update();
+    }
+
+    /// Plays the notes of the instrument in `whistle_key`.
+    pub fn set_instrument(&mut self, instrument: Instrument, whistle_key: WhistleKey) {
+        self.instrument = instrument;
+        self.whistle_key = whistle_key;
+        self.update();
+    }
+
+    /// Handles the keys of the whistle, returns true when the event is used up.
+    pub fn window_event(&mut self, event: &WindowEvent) -> bool {
+        let WindowEvent::KeyboardInput {
+            event:
+                KeyEvent {
+                    physical_key: PhysicalKey::Code(code),
+                    state,
+                    repeat,
+                    ..
+                },
+            ..
+        } = event
+        else {
+            return false;
+        };
+        let pressed = *state == ElementState::Pressed;
+        if *code == TOGGLE_KEY {
+            if pressed && !repeat {
+                self.set_enabled(!self.enabled);
+            }
+            return true;
+        }
+        if !self.enabled {
+            return false;
+        }
+
+        if let Some(hole) = HOLE_KEYS.iter().position(|key| key == code) {
+            let bit = 1 << (5 - hole);
+            if pressed {
+                self.holes |= bit;
+            } else {
+                self.holes &= !bit;
+            }
+        } else if matches!(code, KeyCode::ShiftLeft | KeyCode::ShiftRight) {
+            self.overblow = pressed;
+        } else if *code == BREATH_KEY {
+            self.breath = pressed;
+        } else {
+            return false;
+        }
+        if !repeat {
+            self.update();
+        }
+        true
+    }
+
+    /// Sends the note for the fingering when it changed.
+    fn update(&mut self) {
+        let note = if self.breath {
+            self.note(self.overblow)
+        } else {
+            None
+        };
+        if note == self.sounding {
+            return;
+        }
+        if let Some(key) = self.sounding.take() {
+            self.send(MidiMessage::NoteOff {
+                key: u7::new(key),
+                vel: u7::new(0),
+            });
+        }
+        if let Some(key) = note {
+            self.send(MidiMessage::NoteOn {
+                key: u7::new(key),
+                vel: u7::new(VELOCITY),
+            });
+        }
+        self.sounding = note;
+    }
+
+    fn send(&self, message: MidiMessage) {
+        self.tx
+            .send_event(WhstlrsEvent::MidiInput {
+                channel: CHANNEL,
+                message,
+            })
+            .ok();
+    }
+
+    /// The note of the closed holes on the instrument.
+    fn note(&self, overblow: bool) -> Option<u8> {
+        // the keys close the top holes, the others stay open
+        let holes = self.instrument.holes;
+        let closed = if holes >= HOLE_KEYS.len() {
+            self.holes << (holes - HOLE_KEYS.len())
+        } else {
+            self.holes >> (HOLE_KEYS.len() - holes)
+        };
+        let mask = closed | (overblow as u16) << holes;
+        self.instrument.note_of(mask, self.whistle_key).or_else(|| {
+            // fingerings without their own overblown entry sound an octave up
+            overblow
+                .then(|| self.note(false))
+                .flatten()
+                .map(|key| key + 12)
+        })
+    }
+}
@@ -0,0 +1,73 @@
+use std::fmt;
+
+/// Lowest note of the D whistle.
+const D_BOTTOM: u8 = 62;
+
+/// The key of a whistle, named after its lowest note with all holes closed.
+/// The fingering charts are moved from D to the key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WhistleKey {
+    #[default]
+    D,
+    C,
+    Eb,
+    Bb,
+    /// Reads and fingers the same music as the D whistle, an octave lower.
+    LowD,
+}
+
+impl WhistleKey {
+    pub const ALL: [WhistleKey; 5] = [
+        WhistleKey::D,
+        WhistleKey::C,
+        WhistleKey::Eb,
+        WhistleKey::Bb,
+        WhistleKey::LowD,
+    ];
+
+    /// The key by its name, like "Eb" or "low-d".
+    pub fn parse(text: &str) -> Option<Self> {
+        let text = text.trim().to_lowercase().replace([' ', '-', '_'], "");
+        Self::ALL
+            .into_iter()
+            .find(|key| key.name().to_lowercase().replace(' ', "") == text)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            WhistleKey::D => "D",
+            WhistleKey::C => "C",
+            WhistleKey::Eb => "Eb",
+            WhistleKey::Bb => "Bb",
+            WhistleKey::LowD => "Low D",
+        }
+    }
+
+    /// The following key, to cycle through them.
+    pub fn next(&self) -> Self {
+        let index = Self::ALL.iter().position(|key| key == self).unwrap_or(0);
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+
+    /// The lowest note as written in the songs. Whistle music is written an
+    /// octave below the sound, like that of the D whistle.
+    pub fn bottom(&self) -> u8 {
+        match self {
+            WhistleKey::D | WhistleKey::LowD => 62,
+            WhistleKey::C => 60,
+            WhistleKey::Eb => 63,
+            WhistleKey::Bb => 58,
+        }
+    }
+
+    /// Semitones from the D whistle to this one.
+    pub fn transpose(&self) -> i8 {
+        self.bottom() as i8 - D_BOTTOM as i8
+    }
+}
+
+impl fmt::Display for WhistleKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} whistle", self.name())
+    }
+}